@@ -0,0 +1,53 @@
+use std::fmt::Display;
+
+/// A construct the compiler recognised, printed by `rlox --ast`. The compiler emits bytecode
+/// as it parses and never builds a syntax tree of its own, so this is a record of the parse
+/// rather than something later passes consume.
+#[derive(Debug)]
+pub struct Node {
+    pub label: String,
+    pub children: Vec<Self>,
+}
+
+/// Assembles [`Node`]s bottom-up. A construct is only known to be complete once its last
+/// token has been parsed, so each node is built from the nodes finished since a [`Self::mark`]
+/// taken when the construct began.
+#[derive(Debug, Default)]
+pub struct Builder {
+    nodes: Vec<Node>,
+}
+
+impl Builder {
+    /// Position from which the next node's children start.
+    pub const fn mark(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Replaces every node finished since `mark` with a single node that holds them.
+    pub fn close(&mut self, mark: usize, label: String) {
+        let children = self.nodes.split_off(mark.min(self.nodes.len()));
+        self.nodes.push(Node { label, children });
+    }
+
+    /// Returns the root, the last node to be closed.
+    pub fn finish(mut self) -> Option<Node> {
+        self.nodes.pop()
+    }
+}
+
+impl Node {
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        writeln!(f, "{:indent$}{}", "", self.label, indent = depth * 2)?;
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the tree one node per line, children indented under their parent.
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, 0)
+    }
+}
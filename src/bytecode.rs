@@ -0,0 +1,272 @@
+//! The `.loxc` format written by `rlox compile`, so scripts can be run without being parsed.
+//!
+//! Every integer outside `code` is little-endian whatever the host, so files move freely
+//! between machines. Operands inside `code` are big-endian, as the compiler writes them.
+//! `source_hash` is the FNV-1a hash of the source, used to warn when that has changed since:
+//!
+//! ```text
+//! file     = "LOXC" version:u16 source_hash:u32 function
+//! function = has_name:u8 [name:string] arity:u8
+//!            upvalue_count:u16 (is_local:u8 index:u8)*
+//!            code_len:u32 code:u8*   (opcodes with big-endian operands)
+//!            constant_count:u32 constant*
+//!            location_count:u32 (offset:u32 line:u32 column:u32 start:u32 end:u32)*
+//! constant = 0 number:f64 | 1 string | 2 function
+//! string   = len:u32 utf8:u8*
+//! ```
+
+use std::fmt::Display;
+
+use crate::chunk::{Chunk, Location};
+use crate::heap::{Heap, ObjRef};
+use crate::object::{hash_string, Function, UpvalueRef, MAX_NESTING};
+use crate::scanner::Span;
+use crate::value::{ObjectType, Value};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/// Bumped whenever the layout or the meaning of an opcode changes.
+pub const VERSION: u16 = 1;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const FUNCTION: u8 = 2;
+
+/// Why a file could not be loaded as bytecode.
+#[derive(Debug)]
+pub enum LoadError {
+    NotBytecode,
+    UnsupportedVersion(u16),
+    Truncated,
+    Malformed(&'static str),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotBytecode => write!(f, "Not an rlox bytecode file."),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Bytecode format version {version} is not supported; expected {VERSION}."
+            ),
+            Self::Truncated => write!(f, "Bytecode file is truncated."),
+            Self::Malformed(what) => write!(f, "Malformed bytecode: {what}."),
+        }
+    }
+}
+
+/// Whether `bytes` starts like a bytecode file rather than source code.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Whether the bytecode file `bytes` was compiled from `source`, going by the hash in its
+/// header.
+pub fn compiled_from(bytes: &[u8], source: &str) -> bool {
+    let at = MAGIC.len() + size_of::<u16>();
+    bytes.get(at..at + size_of::<u32>()) == Some(hash_string(source).to_le_bytes().as_slice())
+}
+
+/// Serializes the script `function`, compiled from `source`, and every function nested in it.
+pub fn write(heap: &Heap, function: ObjRef, source: &str) -> Vec<u8> {
+    let mut writer = Writer {
+        heap,
+        out: Vec::new(),
+    };
+    writer.out.extend_from_slice(MAGIC);
+    writer.out.extend_from_slice(&VERSION.to_le_bytes());
+    writer.u32(hash_string(source));
+    writer.function(function);
+    writer.out
+}
+
+/// Loads the script function from `bytes`, allocating its strings and functions on `heap`.
+pub fn read(heap: &mut Heap, bytes: &[u8]) -> Result<ObjRef, LoadError> {
+    let mut reader = Reader { heap, bytes };
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(LoadError::NotBytecode);
+    }
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    // Only checked against the source by `compiled_from`.
+    let _source_hash = reader.u32()?;
+    let function = reader.function(0)?;
+    if !reader.bytes.is_empty() {
+        return Err(LoadError::Malformed("trailing bytes after the script"));
+    }
+    Ok(function)
+}
+
+struct Writer<'heap> {
+    heap: &'heap Heap,
+    out: Vec<u8>,
+}
+
+impl Writer<'_> {
+    fn u32(&mut self, value: u32) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a length or offset, which the compiler keeps far below `u32::MAX`.
+    fn len(&mut self, len: usize) {
+        self.u32(u32::try_from(len).expect("Chunk too large to serialize."));
+    }
+
+    fn string(&mut self, string: ObjRef) {
+        let chars = self.heap.string(string);
+        self.len(chars.len());
+        self.out.extend_from_slice(chars.as_bytes());
+    }
+
+    fn function(&mut self, function: ObjRef) {
+        let heap = self.heap;
+        let function = heap.function(function);
+        match function.name {
+            Some(name) => {
+                self.out.push(1);
+                self.string(name);
+            }
+            None => self.out.push(0),
+        }
+        self.out
+            .push(u8::try_from(function.arity).expect("Arity is limited to 255."));
+        let upvalues = &function.upvalues;
+        let count = u16::try_from(upvalues.len()).expect("Upvalues are limited to 256.");
+        self.out.extend_from_slice(&count.to_le_bytes());
+        for upvalue in upvalues {
+            self.out.push(u8::from(upvalue.is_local));
+            self.out
+                .push(u8::try_from(upvalue.index).expect("Upvalue indices fit in a byte."));
+        }
+
+        let chunk = &function.chunk;
+        self.len(chunk.code.len());
+        self.out.extend_from_slice(&chunk.code);
+        self.len(chunk.constants().len());
+        for &constant in chunk.constants() {
+            match constant {
+                Value::Number(number) => {
+                    self.out.push(NUMBER);
+                    self.out.extend_from_slice(&number.to_bits().to_le_bytes());
+                }
+                Value::Object(object) => match heap.get(object) {
+                    ObjectType::String(_) => {
+                        self.out.push(STRING);
+                        self.string(object);
+                    }
+                    ObjectType::Function(_) => {
+                        self.out.push(FUNCTION);
+                        self.function(object);
+                    }
+                    other => panic!("Unexpected constant {other:?}."),
+                },
+                other => panic!("Unexpected constant {other:?}."),
+            }
+        }
+        self.len(chunk.locations().len());
+        for &(offset, location) in chunk.locations() {
+            self.len(offset);
+            self.u32(location.line);
+            self.u32(location.column);
+            self.len(location.span.start);
+            self.len(location.span.end);
+        }
+    }
+}
+
+struct Reader<'heap, 'bytes> {
+    heap: &'heap mut Heap,
+    bytes: &'bytes [u8],
+}
+
+impl<'bytes> Reader<'_, 'bytes> {
+    const fn take(&mut self, len: usize) -> Result<&'bytes [u8], LoadError> {
+        if self.bytes.len() < len {
+            return Err(LoadError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        usize::try_from(self.u32()?).map_err(|_| LoadError::Malformed("length out of range"))
+    }
+
+    fn string(&mut self) -> Result<ObjRef, LoadError> {
+        let len = self.len()?;
+        let chars = std::str::from_utf8(self.take(len)?)
+            .map_err(|_| LoadError::Malformed("string is not UTF-8"))?;
+        Ok(self.heap.intern(chars))
+    }
+
+    /// Reads a function nested `depth` deep, the script being at zero.
+    fn function(&mut self, depth: usize) -> Result<ObjRef, LoadError> {
+        if depth > MAX_NESTING {
+            return Err(LoadError::Malformed("functions nested too deeply"));
+        }
+        let name = match self.u8()? {
+            0 => None,
+            1 => Some(self.string()?),
+            _ => return Err(LoadError::Malformed("invalid function name flag")),
+        };
+        let mut function = Function::new(name);
+        function.arity = usize::from(self.u8()?);
+        let upvalue_count = u16::from_le_bytes(self.array()?);
+        for _ in 0..upvalue_count {
+            let is_local = match self.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(LoadError::Malformed("invalid upvalue flag")),
+            };
+            let index = usize::from(self.u8()?);
+            function.upvalues.push(UpvalueRef { is_local, index });
+        }
+
+        let code_len = self.len()?;
+        let code = self.take(code_len)?.to_vec();
+        let constant_count = self.len()?;
+        let mut constants = Vec::new();
+        for _ in 0..constant_count {
+            let constant = match self.u8()? {
+                NUMBER => Value::Number(f64::from_bits(u64::from_le_bytes(self.array()?))),
+                STRING => Value::Object(self.string()?),
+                FUNCTION => Value::Object(self.function(depth + 1)?),
+                _ => return Err(LoadError::Malformed("unknown constant tag")),
+            };
+            constants.push(constant);
+        }
+        let location_count = self.len()?;
+        let mut locations = Vec::new();
+        for _ in 0..location_count {
+            let offset = self.len()?;
+            let line = self.u32()?;
+            let column = self.u32()?;
+            let span = Span::new(self.len()?, self.len()?);
+            locations.push((offset, Location { line, column, span }));
+        }
+        if locations.first().is_some_and(|&(offset, _)| offset != 0)
+            || locations.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+            || (locations.is_empty() && !code.is_empty())
+        {
+            return Err(LoadError::Malformed("location table out of order"));
+        }
+        function.chunk = Chunk::from_parts(code, constants, locations);
+        Ok(self.heap.alloc(ObjectType::Function(function)))
+    }
+}
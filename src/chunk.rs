@@ -1,17 +1,37 @@
-use std::fmt::Display;
+use std::collections::HashMap;
+use std::mem::size_of;
 
-use crate::value::{Constant, Line};
+use crate::heap::ObjRef;
+use crate::scanner::{Span, Token};
+use crate::value::{Column, Line, Value};
 
-#[derive(Debug, Clone, Copy)]
+/// An instruction's opcode, stored as a single byte in [`Chunk::code`] and followed by
+/// [`Self::operand_width`] bytes of big-endian operand.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
-    Constant(usize),
+    Constant,
+    ConstantLong,
     Nil,
     True,
     False,
     Pop,
-    GetGlobal(usize),
-    DefineGlobal(usize),
-    SetGlobal(usize),
+    GetGlobal,
+    GetGlobalLong,
+    DefineGlobal,
+    DefineGlobalLong,
+    SetGlobal,
+    SetGlobalLong,
+    GetLocal,
+    SetLocal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    GetPropertyLong,
+    SetProperty,
+    SetPropertyLong,
+    GetSuper,
+    GetSuperLong,
     Equal,
     Greater,
     Less,
@@ -22,76 +42,364 @@ pub enum OpCode {
     Not,
     Negate,
     Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Closure,
+    ClosureLong,
+    CloseUpvalue,
     Return,
+    Class,
+    ClassLong,
+    Inherit,
+    Method,
+    MethodLong,
+    // Superinstructions, which the peephole pass in [`crate::optimizer`] makes out of pairs of
+    // the instructions above.
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
+    AddConstant,
+    AddConstantLong,
+}
+
+impl OpCode {
+    /// Every opcode, indexed by its byte.
+    const ALL: [Self; 50] = [
+        Self::Constant,
+        Self::ConstantLong,
+        Self::Nil,
+        Self::True,
+        Self::False,
+        Self::Pop,
+        Self::GetGlobal,
+        Self::GetGlobalLong,
+        Self::DefineGlobal,
+        Self::DefineGlobalLong,
+        Self::SetGlobal,
+        Self::SetGlobalLong,
+        Self::GetLocal,
+        Self::SetLocal,
+        Self::GetUpvalue,
+        Self::SetUpvalue,
+        Self::GetProperty,
+        Self::GetPropertyLong,
+        Self::SetProperty,
+        Self::SetPropertyLong,
+        Self::GetSuper,
+        Self::GetSuperLong,
+        Self::Equal,
+        Self::Greater,
+        Self::Less,
+        Self::Add,
+        Self::Subtract,
+        Self::Multiply,
+        Self::Divide,
+        Self::Not,
+        Self::Negate,
+        Self::Print,
+        Self::Jump,
+        Self::JumpIfFalse,
+        Self::Loop,
+        Self::Call,
+        Self::Closure,
+        Self::ClosureLong,
+        Self::CloseUpvalue,
+        Self::Return,
+        Self::Class,
+        Self::ClassLong,
+        Self::Inherit,
+        Self::Method,
+        Self::MethodLong,
+        Self::NotEqual,
+        Self::GreaterEqual,
+        Self::LessEqual,
+        Self::AddConstant,
+        Self::AddConstantLong,
+    ];
+
+    /// Decodes an opcode byte, or returns `None` if no opcode has that value.
+    pub const fn from_byte(byte: u8) -> Option<Self> {
+        if (byte as usize) < Self::ALL.len() {
+            Some(Self::ALL[byte as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Number of operand bytes following the opcode.
+    pub const fn operand_width(self) -> usize {
+        match self {
+            Self::Constant
+            | Self::GetGlobal
+            | Self::DefineGlobal
+            | Self::SetGlobal
+            | Self::GetLocal
+            | Self::SetLocal
+            | Self::GetUpvalue
+            | Self::SetUpvalue
+            | Self::GetProperty
+            | Self::SetProperty
+            | Self::GetSuper
+            | Self::Call
+            | Self::Closure
+            | Self::Class
+            | Self::Method
+            | Self::AddConstant => 1,
+            Self::Jump | Self::JumpIfFalse | Self::Loop => 2,
+            Self::ConstantLong
+            | Self::GetGlobalLong
+            | Self::DefineGlobalLong
+            | Self::SetGlobalLong
+            | Self::GetPropertyLong
+            | Self::SetPropertyLong
+            | Self::GetSuperLong
+            | Self::ClosureLong
+            | Self::ClassLong
+            | Self::MethodLong
+            | Self::AddConstantLong => 3,
+            Self::Nil
+            | Self::True
+            | Self::False
+            | Self::Pop
+            | Self::Equal
+            | Self::Greater
+            | Self::Less
+            | Self::Add
+            | Self::Subtract
+            | Self::Multiply
+            | Self::Divide
+            | Self::Not
+            | Self::Negate
+            | Self::Print
+            | Self::CloseUpvalue
+            | Self::Return
+            | Self::Inherit
+            | Self::NotEqual
+            | Self::GreaterEqual
+            | Self::LessEqual => 0,
+        }
+    }
+
+    /// The variant of a constant-pool instruction that takes a 24-bit index.
+    pub const fn long(self) -> Option<Self> {
+        match self {
+            Self::Constant => Some(Self::ConstantLong),
+            Self::GetGlobal => Some(Self::GetGlobalLong),
+            Self::DefineGlobal => Some(Self::DefineGlobalLong),
+            Self::SetGlobal => Some(Self::SetGlobalLong),
+            Self::GetProperty => Some(Self::GetPropertyLong),
+            Self::SetProperty => Some(Self::SetPropertyLong),
+            Self::GetSuper => Some(Self::GetSuperLong),
+            Self::Closure => Some(Self::ClosureLong),
+            Self::Class => Some(Self::ClassLong),
+            Self::Method => Some(Self::MethodLong),
+            Self::AddConstant => Some(Self::AddConstantLong),
+            _ => None,
+        }
+    }
+}
+
+/// Where in the source the code for an instruction came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Location {
+    pub line: Line,
+    pub column: Column,
+    pub span: Span,
+}
+
+impl Location {
+    pub const fn of(token: &Token) -> Self {
+        Self {
+            line: token.line,
+            column: token.column,
+            span: token.span,
+        }
+    }
+}
+
+/// What makes two constants the same pool entry. Numbers are compared by their bits, so `0`
+/// and `-0` get separate entries, as do NaNs with different payloads. Objects are compared by
+/// handle, which for interned strings means by contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    Object(ObjRef),
+}
+
+impl ConstantKey {
+    const fn of(value: Value) -> Option<Self> {
+        match value {
+            Value::Number(number) => Some(Self::Number(number.to_bits())),
+            Value::Object(object) => Some(Self::Object(object)),
+            Value::Bool(_) | Value::Nil => None,
+        }
+    }
+}
+
+/// How far a chunk had got, for [`Chunk::rewind`].
+#[derive(Debug, Clone, Copy)]
+pub struct Mark {
+    pub code: usize,
+    constants: usize,
 }
 
 #[derive(Debug)]
-pub struct Chunk<'src> {
-    pub code: Vec<OpCode>,
-    constants: Vec<Constant<'src>>,
-    lines: Vec<Line>,
+pub struct Chunk {
+    /// Opcodes interleaved with their operands.
+    pub code: Vec<u8>,
+    constants: Vec<Value>,
+    /// Run-length encoded locations: each entry covers the instructions from its offset up to
+    /// the next entry's, so runs of code from one token cost a single entry.
+    locations: Vec<(usize, Location)>,
+    /// Index of each constant in the pool, so adding one does not scan the whole pool.
+    pool_index: HashMap<ConstantKey, usize>,
 }
 
-impl<'src> Chunk<'src> {
+impl Chunk {
     pub fn new() -> Self {
-        let code = Vec::new();
-        let constants = Vec::new();
-        let lines = Vec::new();
+        Self::default()
+    }
+
+    /// Reassembles a chunk from its serialized parts; see [`crate::bytecode`].
+    pub fn from_parts(
+        code: Vec<u8>,
+        constants: Vec<Value>,
+        locations: Vec<(usize, Location)>,
+    ) -> Self {
+        let mut pool_index = HashMap::new();
+        for (index, &constant) in constants.iter().enumerate() {
+            if let Some(key) = ConstantKey::of(constant) {
+                pool_index.entry(key).or_insert(index);
+            }
+        }
         Self {
             code,
             constants,
-            lines,
+            locations,
+            pool_index,
         }
     }
 
-    pub fn write(&mut self, op: OpCode, line: Line) {
-        self.code.push(op);
-        self.lines.push(line);
-        assert_eq!(self.code.len(), self.lines.len());
+    pub fn write(&mut self, byte: u8, location: Location) {
+        if self
+            .locations
+            .last()
+            .is_none_or(|&(_, last)| last != location)
+        {
+            self.locations.push((self.code.len(), location));
+        }
+        self.code.push(byte);
     }
 
-    pub fn constant(&self, index: usize) -> Constant {
-        self.constants[index]
+    /// Writes `operand` as `width` big-endian bytes. The caller checks that it fits.
+    pub fn write_operand(&mut self, operand: usize, width: usize, location: Location) {
+        for &byte in &operand.to_be_bytes()[size_of::<usize>() - width..] {
+            self.write(byte, location);
+        }
     }
 
-    pub fn add_constant(&mut self, constant: Constant<'src>) -> usize {
-        self.constants.push(constant);
-        self.constants.len() - 1
+    /// Reads the `width`-byte operand starting at `offset`.
+    pub fn read_operand(&self, offset: usize, width: usize) -> usize {
+        self.code[offset..offset + width]
+            .iter()
+            .fold(0, |operand, &byte| operand << 8 | usize::from(byte))
+    }
+
+    pub const fn mark(&self) -> Mark {
+        Mark {
+            code: self.code.len(),
+            constants: self.constants.len(),
+        }
     }
-}
 
-impl<'src> Display for Chunk<'src> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut curr_line = 0;
-        for (i, op) in self.code.iter().enumerate() {
-            let mut output: String;
-            let line = self.lines[i];
-            if line == curr_line {
-                output = ("| ").to_string();
-            } else {
-                curr_line = line;
-                output = format!("{line} ");
+    /// Removes the code written since `mark`, with its locations, and the constants added
+    /// since, which only that code can have used.
+    pub fn rewind(&mut self, mark: Mark) {
+        self.code.truncate(mark.code);
+        let runs = self
+            .locations
+            .partition_point(|&(start, _)| start < mark.code);
+        self.locations.truncate(runs);
+        for constant in self.constants.drain(mark.constants..) {
+            if let Some(key) = ConstantKey::of(constant) {
+                self.pool_index.remove(&key);
             }
-            output.push_str(format!("{op:?}").as_str());
-            if let OpCode::Constant(index) = op {
-                let constant = self.constant(*index);
-                output.push_str(format!("    {constant:?}").as_str());
+        }
+    }
+
+    /// Replaces the code and its locations with those of `other`, keeping the constants.
+    pub fn replace_code(&mut self, other: Self) {
+        self.code = other.code;
+        self.locations = other.locations;
+    }
+
+    /// Overwrites the 16-bit jump operand at `offset`.
+    pub fn patch_jump(&mut self, offset: usize, jump: u16) {
+        self.code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+    }
+
+    /// Source location of the instruction whose opcode or operand is at `offset`.
+    pub fn location(&self, offset: usize) -> Location {
+        let run = self
+            .locations
+            .partition_point(|&(start, _)| start <= offset);
+        self.locations[run - 1].1
+    }
+
+    pub fn line(&self, offset: usize) -> Line {
+        self.location(offset).line
+    }
+
+    pub fn constant(&self, index: usize) -> Value {
+        self.constants[index]
+    }
+
+    /// Approximate number of bytes owned by the chunk.
+    pub fn size(&self) -> usize {
+        self.code.capacity()
+            + self.constants.capacity() * size_of::<Value>()
+            + self.locations.capacity() * size_of::<(usize, Location)>()
+            + self.pool_index.capacity() * size_of::<(ConstantKey, usize)>()
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    /// The run-length encoded location table, as `(first offset, location)` runs.
+    pub fn locations(&self) -> &[(usize, Location)] {
+        &self.locations
+    }
+
+    /// Adds `constant` to the pool, reusing the existing entry for the same number or object;
+    /// see [`ConstantKey`]. Interned strings make this cover repeated identifiers and string
+    /// literals.
+    pub fn add_constant(&mut self, constant: Value) -> usize {
+        let index = self.constants.len();
+        if let Some(key) = ConstantKey::of(constant) {
+            if let Some(&existing) = self.pool_index.get(&key) {
+                return existing;
             }
-            writeln!(f, "{output}")?;
+            self.pool_index.insert(key, index);
         }
-        Ok(())
+        self.constants.push(constant);
+        index
     }
 }
 
-impl<'src> Default for Chunk<'src> {
+impl Default for Chunk {
     fn default() -> Self {
         let code = Vec::new();
         let constants = Vec::new();
-        let lines = Vec::new();
+        let locations = Vec::new();
+        let pool_index = HashMap::new();
         Self {
             code,
             constants,
-            lines,
+            locations,
+            pool_index,
         }
     }
 }
@@ -0,0 +1,538 @@
+use std::fmt::Display;
+
+use crate::scanner::TokenKind;
+
+/// Stable identifier for a class of error, shown as `L0001` and friends. A code keeps its
+/// meaning when messages are reworded, and retired codes are never reused. Compile errors are
+/// numbered from `L0001`, runtime errors from `L0101`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    MissingSemicolon,
+    MissingParen,
+    MissingBrace,
+    ExpectedName,
+    ExpectedExpression,
+    BareSuper,
+    InvalidAssignmentTarget,
+    DuplicateLocal,
+    LocalInOwnInitializer,
+    TopLevelReturn,
+    ReturnFromInitializer,
+    InheritFromSelf,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    LimitExceeded,
+    UnterminatedString,
+    UnexpectedCharacter,
+    UndefinedVariable,
+    UndefinedProperty,
+    NotAnInstance,
+    TypeMismatch,
+    NotCallable,
+    ArityMismatch,
+    StackOverflow,
+    SuperclassNotClass,
+}
+
+impl ErrorCode {
+    pub const ALL: [Self; 26] = [
+        Self::MissingSemicolon,
+        Self::MissingParen,
+        Self::MissingBrace,
+        Self::ExpectedName,
+        Self::ExpectedExpression,
+        Self::BareSuper,
+        Self::InvalidAssignmentTarget,
+        Self::DuplicateLocal,
+        Self::LocalInOwnInitializer,
+        Self::TopLevelReturn,
+        Self::ReturnFromInitializer,
+        Self::InheritFromSelf,
+        Self::ThisOutsideClass,
+        Self::SuperOutsideClass,
+        Self::SuperWithoutSuperclass,
+        Self::LimitExceeded,
+        Self::UnterminatedString,
+        Self::UnexpectedCharacter,
+        Self::UndefinedVariable,
+        Self::UndefinedProperty,
+        Self::NotAnInstance,
+        Self::TypeMismatch,
+        Self::NotCallable,
+        Self::ArityMismatch,
+        Self::StackOverflow,
+        Self::SuperclassNotClass,
+    ];
+
+    /// The code for failing to find a token of `kind` where the grammar requires one.
+    pub const fn expected(kind: TokenKind) -> Self {
+        match kind {
+            TokenKind::Semicolon => Self::MissingSemicolon,
+            TokenKind::LeftParen | TokenKind::RightParen => Self::MissingParen,
+            TokenKind::LeftBrace | TokenKind::RightBrace => Self::MissingBrace,
+            TokenKind::Identifier => Self::ExpectedName,
+            TokenKind::Dot => Self::BareSuper,
+            _ => Self::ExpectedExpression,
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|error| error.code() == code)
+    }
+
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::MissingSemicolon => "L0001",
+            Self::MissingParen => "L0002",
+            Self::MissingBrace => "L0003",
+            Self::ExpectedName => "L0004",
+            Self::ExpectedExpression => "L0005",
+            Self::BareSuper => "L0006",
+            Self::InvalidAssignmentTarget => "L0007",
+            Self::DuplicateLocal => "L0008",
+            Self::LocalInOwnInitializer => "L0009",
+            Self::TopLevelReturn => "L0010",
+            Self::ReturnFromInitializer => "L0011",
+            Self::InheritFromSelf => "L0012",
+            Self::ThisOutsideClass => "L0013",
+            Self::SuperOutsideClass => "L0014",
+            Self::SuperWithoutSuperclass => "L0015",
+            Self::LimitExceeded => "L0016",
+            Self::UnterminatedString => "L0017",
+            Self::UnexpectedCharacter => "L0018",
+            Self::UndefinedVariable => "L0101",
+            Self::UndefinedProperty => "L0102",
+            Self::NotAnInstance => "L0103",
+            Self::TypeMismatch => "L0104",
+            Self::NotCallable => "L0105",
+            Self::ArityMismatch => "L0106",
+            Self::StackOverflow => "L0107",
+            Self::SuperclassNotClass => "L0108",
+        }
+    }
+
+    /// The long-form description printed by `rlox --explain`.
+    #[allow(clippy::too_many_lines)]
+    pub const fn explanation(self) -> &'static str {
+        match self {
+            Self::MissingSemicolon => {
+                "\
+A statement is missing its terminating semicolon.
+
+Expression statements, `print` statements, variable declarations and `return`
+statements all end with `;`, as do the first two clauses of a `for` loop.
+
+Erroneous code example:
+
+    var answer = 42
+    print answer;
+
+Add the semicolon:
+
+    var answer = 42;
+    print answer;
+"
+            }
+            Self::MissingParen => {
+                "\
+A parenthesis the grammar requires is missing.
+
+Conditions of `if` and `while`, the clauses of `for`, parameter lists, argument
+lists and grouped expressions are all wrapped in parentheses.
+
+Erroneous code example:
+
+    if answer == 42 print \"yes\";
+
+Wrap the condition in parentheses:
+
+    if (answer == 42) print \"yes\";
+"
+            }
+            Self::MissingBrace => {
+                "\
+A brace the grammar requires is missing.
+
+Blocks, function bodies and class bodies are wrapped in `{` and `}`.
+
+Erroneous code example:
+
+    fun greet() print \"hi\";
+
+Put the body in a block:
+
+    fun greet() { print \"hi\"; }
+"
+            }
+            Self::ExpectedName => {
+                "\
+An identifier was expected but something else was found.
+
+Declarations need a name for the variable, function, parameter, class or method
+they declare, and `.` must be followed by the name of a property.
+
+Erroneous code example:
+
+    var 42 = \"answer\";
+
+Name the variable with an identifier:
+
+    var answer = 42;
+"
+            }
+            Self::ExpectedExpression => {
+                "\
+An expression was expected but something else was found.
+
+This usually means an operator is missing an operand, or a statement was cut off.
+
+Erroneous code example:
+
+    print 1 + ;
+
+Give the operator both operands:
+
+    print 1 + 2;
+"
+            }
+            Self::BareSuper => {
+                "\
+`super` was used on its own instead of to access a method.
+
+`super` is only meaningful as `super.method`, which looks the method up on the
+superclass of the enclosing class.
+
+Erroneous code example:
+
+    class B < A { m() { return super; } }
+
+Access a method through it:
+
+    class B < A { m() { return super.m(); } }
+"
+            }
+            Self::InvalidAssignmentTarget => {
+                "\
+The left-hand side of `=` is not something that can be assigned to.
+
+Only variables and properties can be assigned. This error often means `=` was
+written where the comparison `==` was meant.
+
+Erroneous code example:
+
+    if (a + b = c) print \"equal\";
+
+Compare instead of assigning:
+
+    if (a + b == c) print \"equal\";
+"
+            }
+            Self::DuplicateLocal => {
+                "\
+A local variable was declared twice in the same scope.
+
+Globals may be redeclared, but within a block each name can only be declared once.
+
+Erroneous code example:
+
+    {
+      var a = 1;
+      var a = 2;
+    }
+
+Assign to the existing variable instead:
+
+    {
+      var a = 1;
+      a = 2;
+    }
+"
+            }
+            Self::LocalInOwnInitializer => {
+                "\
+A local variable was read in its own initializer.
+
+A local variable only comes into scope once its initializer has run, so the
+initializer cannot refer to it.
+
+Erroneous code example:
+
+    {
+      var a = a + 1;
+    }
+
+Initialize it from something already in scope:
+
+    {
+      var b = 1;
+      var a = b + 1;
+    }
+"
+            }
+            Self::TopLevelReturn => {
+                "\
+`return` was used outside of a function.
+
+Top-level code is not a function, so there is nothing to return from.
+
+Erroneous code example:
+
+    return 42;
+
+Only return from inside a function:
+
+    fun answer() { return 42; }
+"
+            }
+            Self::ReturnFromInitializer => {
+                "\
+An initializer returned a value.
+
+`init` always returns the new instance, so it may only use a bare `return;`.
+
+Erroneous code example:
+
+    class Point { init() { return 0; } }
+
+Return without a value:
+
+    class Point { init() { return; } }
+"
+            }
+            Self::InheritFromSelf => {
+                "\
+A class was declared as its own superclass.
+
+Erroneous code example:
+
+    class A < A {}
+
+Inherit from another class:
+
+    class Base {}
+    class A < Base {}
+"
+            }
+            Self::ThisOutsideClass => {
+                "\
+`this` was used outside of a method.
+
+`this` refers to the instance a method was called on, so it only exists inside
+the methods of a class.
+
+Erroneous code example:
+
+    print this;
+
+Use it inside a method:
+
+    class A { show() { print this; } }
+"
+            }
+            Self::SuperOutsideClass => {
+                "\
+`super` was used outside of a method.
+
+Erroneous code example:
+
+    super.show();
+
+Use it inside a method of a subclass:
+
+    class B < A { show() { super.show(); } }
+"
+            }
+            Self::SuperWithoutSuperclass => {
+                "\
+`super` was used in a class that has no superclass.
+
+Erroneous code example:
+
+    class A { show() { super.show(); } }
+
+Give the class a superclass:
+
+    class A < Base { show() { super.show(); } }
+"
+            }
+            Self::LimitExceeded => {
+                "\
+Code went over one of the virtual machine's fixed limits.
+
+A function may have at most 255 parameters, a call at most 255 arguments, and a
+function may have at most 256 local variables in scope at once and capture at
+most 256 variables from enclosing functions. Jumps are limited to 65535 bytes of
+bytecode, so very long `if` bodies or loops can hit this too, and a function may
+use at most 16777216 distinct constants. Functions may be nested at most 256
+deep.
+
+Split the work across several functions, or pass the values in an instance
+instead of as separate arguments.
+"
+            }
+            Self::UnterminatedString => {
+                "\
+A string literal was never closed.
+
+Strings may span several lines, so the missing `\"` is usually reported where the
+string starts.
+
+Erroneous code example:
+
+    print \"hello;
+
+Close the string:
+
+    print \"hello\";
+"
+            }
+            Self::UnexpectedCharacter => {
+                "\
+The source contains a character that is not part of Lox.
+
+Erroneous code example:
+
+    var price = 5 $;
+
+Remove the character:
+
+    var price = 5;
+"
+            }
+            Self::UndefinedVariable => {
+                "\
+A global variable was read or assigned before it was declared.
+
+Erroneous code example:
+
+    print answer;
+
+Declare it first:
+
+    var answer = 42;
+    print answer;
+"
+            }
+            Self::UndefinedProperty => {
+                "\
+An instance has neither a field nor a method with the name that was accessed.
+
+Erroneous code example:
+
+    class Point {}
+    print Point().x;
+
+Set the field before reading it:
+
+    class Point { init() { this.x = 0; } }
+    print Point().x;
+"
+            }
+            Self::NotAnInstance => {
+                "\
+A property was read or set on a value that is not an instance.
+
+Only instances have fields and methods.
+
+Erroneous code example:
+
+    var n = 1;
+    n.x = 2;
+
+Use an instance instead:
+
+    class Box {}
+    var b = Box();
+    b.x = 2;
+"
+            }
+            Self::TypeMismatch => {
+                "\
+An operator was applied to values of the wrong type.
+
+Arithmetic and comparison operators need numbers, `-` needs a number, and `+`
+needs two numbers or two strings. Lox never converts between types implicitly.
+
+Erroneous code example:
+
+    print \"total: \" + 42;
+
+Keep both operands the same type:
+
+    print \"total: \" + \"42\";
+"
+            }
+            Self::NotCallable => {
+                "\
+A value that is neither a function nor a class was called.
+
+Erroneous code example:
+
+    var answer = 42;
+    answer();
+
+Only call functions, methods and classes:
+
+    fun answer() { return 42; }
+    answer();
+"
+            }
+            Self::ArityMismatch => {
+                "\
+A function was called with the wrong number of arguments.
+
+Calling a class passes the arguments to its `init` method, which must then accept
+them; a class without `init` takes no arguments.
+
+Erroneous code example:
+
+    fun add(a, b) { return a + b; }
+    add(1);
+
+Pass one argument per parameter:
+
+    add(1, 2);
+"
+            }
+            Self::StackOverflow => {
+                "\
+Calls nested deeper than the virtual machine allows.
+
+This is almost always unbounded recursion.
+
+Erroneous code example:
+
+    fun forever() { forever(); }
+    forever();
+
+Give the recursion a base case:
+
+    fun count(n) { if (n > 0) count(n - 1); }
+    count(10);
+"
+            }
+            Self::SuperclassNotClass => {
+                "\
+A class tried to inherit from a value that is not a class.
+
+Erroneous code example:
+
+    var Base = \"not a class\";
+    class A < Base {}
+
+Inherit from a class:
+
+    class Base {}
+    class A < Base {}
+"
+            }
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
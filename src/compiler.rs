@@ -1,15 +1,21 @@
-use crate::chunk::{Chunk, OpCode};
-use crate::scanner::{Scanner, Token, TokenKind};
-use crate::value::{Constant, Double};
+use crate::ast::{self, Node};
+use crate::chunk::{Chunk, Location, Mark, OpCode};
+use crate::codes::ErrorCode;
+use crate::error::Diagnostic;
+use crate::heap::{Heap, ObjRef};
+use crate::object::{Function, UpvalueRef, MAX_NESTING};
+use crate::optimizer;
+use crate::scanner::{self, Scanner, Token, TokenKind};
+use crate::value::{is_falsey, Double, Line, ObjectType, Value};
 
 #[rustfmt::skip]
 const RULES: [ParseRule; 40] = [
-    ParseRule { _kind: TokenKind::LeftParen, prefix: Some(FunctionRepr::Grouping), infix: None, precedence: Precedence::None, },
+    ParseRule { _kind: TokenKind::LeftParen, prefix: Some(FunctionRepr::Grouping), infix: Some(FunctionRepr::Call), precedence: Precedence::Call, },
     ParseRule { _kind: TokenKind::RightParen, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::LeftBrace, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::RightBrace, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::Comma, prefix: None, infix: None, precedence: Precedence::None, },
-    ParseRule { _kind: TokenKind::Dot, prefix: None, infix: None, precedence: Precedence::None, },
+    ParseRule { _kind: TokenKind::Dot, prefix: None, infix: Some(FunctionRepr::Dot), precedence: Precedence::Call, },
     ParseRule { _kind: TokenKind::Minus, prefix: Some(FunctionRepr::Unary), infix: Some(FunctionRepr::Binary), precedence: Precedence::Term, },
     ParseRule { _kind: TokenKind::Plus, prefix: None, infix: Some(FunctionRepr::Binary), precedence: Precedence::Term, },
     ParseRule { _kind: TokenKind::Semicolon, prefix: None, infix: None, precedence: Precedence::None, },
@@ -26,7 +32,7 @@ const RULES: [ParseRule; 40] = [
     ParseRule { _kind: TokenKind::Identifier, prefix: Some(FunctionRepr::Variable), infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::String, prefix: Some(FunctionRepr::String), infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::Number, prefix: Some(FunctionRepr::Number), infix: None, precedence: Precedence::None, },
-    ParseRule { _kind: TokenKind::And, prefix: None, infix: None, precedence: Precedence::None, },
+    ParseRule { _kind: TokenKind::And, prefix: None, infix: Some(FunctionRepr::And), precedence: Precedence::And, },
     ParseRule { _kind: TokenKind::Class, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::Else, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::False, prefix: Some(FunctionRepr::Literal), infix: None, precedence: Precedence::None, },
@@ -34,11 +40,11 @@ const RULES: [ParseRule; 40] = [
     ParseRule { _kind: TokenKind::Fun, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::If, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::Nil, prefix: Some(FunctionRepr::Literal), infix: None, precedence: Precedence::None, },
-    ParseRule { _kind: TokenKind::Or, prefix: None, infix: None, precedence: Precedence::None, },
+    ParseRule { _kind: TokenKind::Or, prefix: None, infix: Some(FunctionRepr::Or), precedence: Precedence::Or, },
     ParseRule { _kind: TokenKind::Print, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::Return, prefix: None, infix: None, precedence: Precedence::None, },
-    ParseRule { _kind: TokenKind::Super, prefix: None, infix: None, precedence: Precedence::None, },
-    ParseRule { _kind: TokenKind::This, prefix: None, infix: None, precedence: Precedence::None, },
+    ParseRule { _kind: TokenKind::Super, prefix: Some(FunctionRepr::Super), infix: None, precedence: Precedence::None, },
+    ParseRule { _kind: TokenKind::This, prefix: Some(FunctionRepr::This), infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::True, prefix: Some(FunctionRepr::Literal), infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::Var, prefix: None, infix: None, precedence: Precedence::None, },
     ParseRule { _kind: TokenKind::While, prefix: None, infix: None, precedence: Precedence::None, },
@@ -89,6 +95,12 @@ enum FunctionRepr {
     Literal,
     String,
     Variable,
+    And,
+    Or,
+    Call,
+    Dot,
+    This,
+    Super,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -103,16 +115,16 @@ struct ParseRule {
 struct Parser<'src> {
     current: Token<'src>,
     previous: Token<'src>,
-    had_error: bool,
+    diagnostics: Vec<Diagnostic>,
     panic_mode: bool,
 }
 
-impl<'src> Parser<'src> {
+impl Parser<'_> {
     fn new() -> Self {
         Self {
             current: Token::default(),
             previous: Token::default(),
-            had_error: false,
+            diagnostics: Vec::new(),
             panic_mode: false,
         }
     }
@@ -122,42 +134,191 @@ impl<'src> Parser<'src> {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+struct Local<'src> {
+    name: Token<'src>,
+    /// Scope depth of the declaring block, or `None` while the initializer is being compiled.
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+/// Compilation state for a single function body. Nested function declarations push a new state
+/// onto [`Compiler::functions`] and pop it once their body has been compiled.
 #[derive(Debug)]
-pub struct Compiler<'src> {
+struct FunctionState<'src> {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local<'src>>,
+    scope_depth: usize,
+}
+
+impl FunctionState<'_> {
+    fn new(kind: FunctionKind, name: Option<ObjRef>) -> Self {
+        // Slot zero holds the function being called, or the receiver in methods.
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => Token::synthetic("this"),
+            FunctionKind::Function | FunctionKind::Script => Token::default(),
+        };
+        let locals = vec![Local {
+            name: slot_zero,
+            depth: Some(0),
+            is_captured: false,
+        }];
+        Self {
+            function: Function::new(name),
+            kind,
+            locals,
+            scope_depth: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClassState {
+    has_superclass: bool,
+}
+
+/// Single-pass compiler from source to bytecode. Strings and functions it creates are
+/// allocated on `heap`; no collection happens while compiling, so they need no rooting until
+/// the resulting script function is handed to the VM.
+#[derive(Debug)]
+pub struct Compiler<'src, 'heap> {
+    heap: &'heap mut Heap,
     source: &'src str,
     parser: Parser<'src>,
     scanner: Scanner<'src>,
-    chunk: Chunk<'src>,
+    functions: Vec<FunctionState<'src>>,
+    /// Class declarations enclosing the code being compiled, innermost last.
+    classes: Vec<ClassState>,
+    /// Whether top-level expression statements print their value, as the REPL wants.
+    repl: bool,
+    /// Where `source` starts in the text that spans and lines index into; see
+    /// [`Self::set_offset`].
+    offset: usize,
+    lines: Line,
+    /// Whether each function's code goes through [`optimizer::optimize`].
+    optimize: bool,
+    /// The parse tree being recorded for `--ast`, if asked for.
+    ast: Option<ast::Builder>,
 }
 
-impl<'src> Compiler<'src> {
-    pub fn new() -> Self {
+impl<'src, 'heap> Compiler<'src, 'heap> {
+    pub fn new(heap: &'heap mut Heap) -> Self {
         let source = "";
         let parser = Parser::new();
         let scanner = Scanner::new();
-        let chunk = Chunk::new();
+        let functions = Vec::new();
+        let classes = Vec::new();
         Self {
+            heap,
             source,
             parser,
             scanner,
-            chunk,
+            functions,
+            classes,
+            repl: false,
+            offset: 0,
+            lines: 0,
+            optimize: false,
+            ast: None,
         }
     }
 
-    pub fn compile(&mut self, source: &'src str) -> Chunk {
+    /// Makes top-level expression statements print their value and lets the last one omit its
+    /// trailing semicolon.
+    pub const fn set_repl(&mut self, repl: bool) {
+        self.repl = repl;
+    }
+
+    /// Treats the source as starting `offset` bytes and `lines` lines into a longer text, such
+    /// as everything typed into the REPL so far, so that spans and lines refer to that text.
+    pub const fn set_offset(&mut self, offset: usize, lines: Line) {
+        self.offset = offset;
+        self.lines = lines;
+    }
+
+    /// Runs the peephole pass over each function once it is compiled.
+    pub const fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Records the structure of the code as it is parsed, for [`Self::take_ast`].
+    pub fn set_ast(&mut self, record: bool) {
+        self.ast = record.then(ast::Builder::default);
+    }
+
+    /// Returns the parse tree of the last compiled script, if recording was enabled.
+    pub fn take_ast(&mut self) -> Option<Node> {
+        self.ast.take().and_then(ast::Builder::finish)
+    }
+
+    /// Compiles `source` into the script function, or returns every error found in it.
+    pub fn compile(&mut self, source: &'src str) -> Result<ObjRef, Vec<Diagnostic>> {
         self.source = source;
         self.scanner.update_source(self.source);
+        self.functions
+            .push(FunctionState::new(FunctionKind::Script, None));
         self.advance();
+        let mark = self.ast_mark();
         while !self.match_token(TokenKind::Eof) {
             self.declaration();
         }
         self.consume(TokenKind::Eof, "Expect end of expression");
+        self.ast_node(mark, || "Script".to_owned());
+        let function = self.end_function();
+        if !self.parser.diagnostics.is_empty() {
+            return Err(std::mem::take(&mut self.parser.diagnostics));
+        }
+        Ok(self.heap.alloc(ObjectType::Function(function)))
+    }
+
+    fn state(&mut self) -> &mut FunctionState<'src> {
+        self.functions
+            .last_mut()
+            .expect("Compiler should always have a function state.")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn ast_mark(&self) -> usize {
+        self.ast.as_ref().map_or(0, ast::Builder::mark)
+    }
+
+    /// Mark for an infix construct, whose left operand has already been recorded.
+    fn ast_mark_infix(&self) -> usize {
+        self.ast_mark().saturating_sub(1)
+    }
+
+    /// Records a node holding everything recorded since `mark`. The label is only built when
+    /// the tree is being recorded.
+    fn ast_node(&mut self, mark: usize, label: impl FnOnce() -> String) {
+        if let Some(ast) = &mut self.ast {
+            ast.close(mark, label());
+        }
+    }
+
+    fn end_function(&mut self) -> Function {
         self.emit_return();
-        if !self.parser.had_error {
-            println!("Chunk output:");
-            println!("{}", self.chunk);
+        let mut function = self
+            .functions
+            .pop()
+            .expect("Compiler should always have a function state.")
+            .function;
+        // Code with errors is thrown away, and may hold jumps that were never patched.
+        if self.optimize && self.parser.diagnostics.is_empty() {
+            optimizer::optimize(&mut function.chunk);
         }
-        std::mem::take(&mut self.chunk)
+        function
     }
 
     fn advance(&mut self) {
@@ -167,9 +328,11 @@ impl<'src> Compiler<'src> {
             if self.parser.current.kind != TokenKind::Error {
                 break;
             }
-            self.parser.had_error = true;
-            self.parser.panic_mode = true;
-            self.error_at(&self.parser.current, "");
+            let code = match self.parser.current.lexeme {
+                scanner::UNTERMINATED_STRING => ErrorCode::UnterminatedString,
+                _ => ErrorCode::UnexpectedCharacter,
+            };
+            self.error_at_current(code, self.parser.current.lexeme);
         }
     }
 
@@ -178,11 +341,17 @@ impl<'src> Compiler<'src> {
             self.advance();
             return;
         }
-        self.error_at(&self.parser.current, message);
+        self.error_at_current(ErrorCode::expected(kind), message);
+    }
+
+    fn check(&self, kind: TokenKind) -> bool {
+        self.parser.current.kind == kind
     }
 
     fn match_token(&mut self, kind: TokenKind) -> bool {
-        if self.parser.current.kind != kind { return false }
+        if !self.check(kind) {
+            return false;
+        }
         self.advance();
         true
     }
@@ -191,28 +360,271 @@ impl<'src> Compiler<'src> {
         self.parse_precedence(Precedence::Assignment);
     }
 
+    fn class_declaration(&mut self) {
+        let mark = self.ast_mark();
+        self.consume(TokenKind::Identifier, "Expect class name.");
+        let class_name = self.parser.previous;
+        let name_constant = self.identifier_constant(class_name.lexeme);
+        self.declare_variable();
+
+        self.emit_operand(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if self.match_token(TokenKind::Less) {
+            self.consume(TokenKind::Identifier, "Expect superclass name.");
+            self.variable(false);
+            if class_name.lexeme == self.parser.previous.lexeme {
+                self.error(
+                    ErrorCode::InheritFromSelf,
+                    "A class can't inherit from itself.",
+                );
+            }
+
+            self.begin_scope();
+            self.add_local(Token::synthetic("super"));
+            self.define_variable(0);
+
+            self.named_variable(class_name, false);
+            self.emit_byte(OpCode::Inherit);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        self.named_variable(class_name, false);
+        self.consume(TokenKind::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            self.method();
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after class body.");
+        self.emit_byte(OpCode::Pop);
+
+        if self.classes.pop().is_some_and(|class| class.has_superclass) {
+            self.end_scope();
+        }
+        self.ast_node(mark, || format!("Class {}", class_name.lexeme));
+    }
+
+    fn method(&mut self) {
+        self.consume(TokenKind::Identifier, "Expect method name.");
+        let name = self.parser.previous.lexeme;
+        let constant = self.identifier_constant(name);
+        let kind = if name == "init" {
+            FunctionKind::Initializer
+        } else {
+            FunctionKind::Method
+        };
+        self.function(kind);
+        self.emit_operand(OpCode::Method, constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        self.mark_initialized();
+        self.function(FunctionKind::Function);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, kind: FunctionKind) {
+        let mark = self.ast_mark();
+        let lexeme = self.parser.previous.lexeme;
+        let name = self.heap.intern(lexeme);
+        // The script itself is not nested.
+        if self.functions.len() > MAX_NESTING {
+            self.error(ErrorCode::LimitExceeded, "Too many nested functions.");
+        }
+        self.functions.push(FunctionState::new(kind, Some(name)));
+        self.begin_scope();
+
+        self.consume(TokenKind::LeftParen, "Expect '(' after function name.");
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                self.state().function.arity += 1;
+                if self.state().function.arity > 255 {
+                    self.error_at_current(
+                        ErrorCode::LimitExceeded,
+                        "Can't have more than 255 parameters.",
+                    );
+                }
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+                let param = self.parser.previous.lexeme;
+                self.ast_node(self.ast_mark(), || format!("Param {param}"));
+                if !self.match_token(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenKind::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        let function = self.end_function();
+        let function = self.heap.alloc(ObjectType::Function(function));
+        let index = self.make_constant(Value::Object(function));
+        self.emit_operand(OpCode::Closure, index);
+        self.ast_node(mark, || match kind {
+            FunctionKind::Function | FunctionKind::Script => format!("Fun {lexeme}"),
+            FunctionKind::Method | FunctionKind::Initializer => format!("Method {lexeme}"),
+        });
+    }
+
     fn var_declaration(&mut self) {
+        let mark = self.ast_mark();
         let global = self.parse_variable("Expect variable name.");
+        let name = self.parser.previous.lexeme;
 
         if self.match_token(TokenKind::Equal) {
             self.expression();
         } else {
             self.emit_byte(OpCode::Nil);
         }
-        self.consume(TokenKind::Semicolon, "Expect ';' after variable declaration.");
+        self.consume(
+            TokenKind::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
         self.define_variable(global);
+        self.ast_node(mark, || format!("Var {name}"));
+    }
+
+    fn block(&mut self) {
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            self.declaration();
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after block.");
     }
 
     fn expression_statement(&mut self) {
+        let mark = self.ast_mark();
         self.expression();
-        self.consume(TokenKind::Semicolon, "Expect ; after expression.");
+        self.ast_node(mark, || "Expression".to_owned());
+        if self.repl && self.functions.len() == 1 && self.state().scope_depth == 0 {
+            if !self.check(TokenKind::Eof) {
+                self.consume(TokenKind::Semicolon, "Expect ';' after expression.");
+            }
+            self.emit_byte(OpCode::Print);
+            return;
+        }
+        self.consume(TokenKind::Semicolon, "Expect ';' after expression.");
         self.emit_byte(OpCode::Pop);
     }
 
     fn print_statement(&mut self) {
+        let mark = self.ast_mark();
         self.expression();
-        self.consume(TokenKind::Semicolon, "Expect ; after value.");
+        self.consume(TokenKind::Semicolon, "Expect ';' after value.");
         self.emit_byte(OpCode::Print);
+        self.ast_node(mark, || "Print".to_owned());
+    }
+
+    fn return_statement(&mut self) {
+        let mark = self.ast_mark();
+        if self.state().kind == FunctionKind::Script {
+            self.error(
+                ErrorCode::TopLevelReturn,
+                "Can't return from top-level code.",
+            );
+        }
+        if self.match_token(TokenKind::Semicolon) {
+            self.emit_return();
+        } else {
+            if self.state().kind == FunctionKind::Initializer {
+                self.error_with_note(
+                    ErrorCode::ReturnFromInitializer,
+                    "Can't return a value from an initializer.",
+                    "initializers always return `this`",
+                );
+            }
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after return value.");
+            self.emit_byte(OpCode::Return);
+        }
+        self.ast_node(mark, || "Return".to_owned());
+    }
+
+    fn if_statement(&mut self) {
+        let mark = self.ast_mark();
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.statement();
+        let else_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(then_jump);
+        self.emit_byte(OpCode::Pop);
+        if self.match_token(TokenKind::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+        self.ast_node(mark, || "If".to_owned());
+    }
+
+    fn while_statement(&mut self) {
+        let mark = self.ast_mark();
+        let loop_start = self.chunk().code.len();
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::Pop);
+        self.ast_node(mark, || "While".to_owned());
+    }
+
+    fn for_statement(&mut self) {
+        let mark = self.ast_mark();
+        self.begin_scope();
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'for'.");
+        if self.match_token(TokenKind::Semicolon) {
+            // No initializer.
+        } else if self.match_token(TokenKind::Var) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.chunk().code.len();
+        let mut exit_jump = None;
+        if !self.match_token(TokenKind::Semicolon) {
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after loop condition.");
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit_byte(OpCode::Pop);
+        }
+
+        if !self.match_token(TokenKind::RightParen) {
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.chunk().code.len();
+            self.expression();
+            self.emit_byte(OpCode::Pop);
+            self.consume(TokenKind::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_byte(OpCode::Pop);
+        }
+        self.end_scope();
+        self.ast_node(mark, || "For".to_owned());
     }
 
     fn synchronize(&mut self) {
@@ -237,7 +649,11 @@ impl<'src> Compiler<'src> {
     }
 
     fn declaration(&mut self) {
-        if self.match_token(TokenKind::Var) {
+        if self.match_token(TokenKind::Class) {
+            self.class_declaration();
+        } else if self.match_token(TokenKind::Fun) {
+            self.fun_declaration();
+        } else if self.match_token(TokenKind::Var) {
             self.var_declaration();
         } else {
             self.statement();
@@ -251,74 +667,274 @@ impl<'src> Compiler<'src> {
     fn statement(&mut self) {
         if self.match_token(TokenKind::Print) {
             self.print_statement();
+        } else if self.match_token(TokenKind::Return) {
+            self.return_statement();
+        } else if self.match_token(TokenKind::If) {
+            self.if_statement();
+        } else if self.match_token(TokenKind::While) {
+            self.while_statement();
+        } else if self.match_token(TokenKind::For) {
+            self.for_statement();
+        } else if self.match_token(TokenKind::LeftBrace) {
+            let mark = self.ast_mark();
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+            self.ast_node(mark, || "Block".to_owned());
         } else {
             self.expression_statement();
         }
     }
 
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let scope_depth = state.scope_depth;
+        while let Some(local) = self
+            .state()
+            .locals
+            .pop_if(|local| local.depth.is_none_or(|depth| depth > scope_depth))
+        {
+            if local.is_captured {
+                self.emit_byte(OpCode::CloseUpvalue);
+            } else {
+                self.emit_byte(OpCode::Pop);
+            }
+        }
+    }
+
     fn emit_byte(&mut self, op: OpCode) {
-        self.chunk.write(op, self.parser.previous.line);
+        self.emit_byte_at(op, self.parser.previous);
+    }
+
+    /// Emits `op` attributed to `token` rather than to the token just consumed, so errors in
+    /// `op` point at the operator or name responsible for it.
+    fn emit_byte_at(&mut self, op: OpCode, token: Token) {
+        let location = self.location(&token);
+        self.chunk().write(op as u8, location);
+    }
+
+    fn emit_operand(&mut self, op: OpCode, operand: usize) {
+        self.emit_operand_at(op, operand, self.parser.previous);
+    }
+
+    /// Emits `op` followed by `operand`, switching to the long form of `op` when the operand
+    /// does not fit in a byte. Callers enforce the limits of operands without a long form.
+    fn emit_operand_at(&mut self, op: OpCode, operand: usize, token: Token) {
+        let op = match op.long() {
+            Some(long) if operand > usize::from(u8::MAX) => long,
+            _ => op,
+        };
+        self.emit_byte_at(op, token);
+        let location = self.location(&token);
+        self.chunk()
+            .write_operand(operand, op.operand_width(), location);
+    }
+
+    /// Where `token` is, with its line and span moved by [`Self::set_offset`].
+    const fn location(&self, token: &Token) -> Location {
+        let mut location = Location::of(token);
+        location.line += self.lines;
+        location.span = self.span(token);
+        location
+    }
+
+    const fn span(&self, token: &Token) -> scanner::Span {
+        scanner::Span::new(token.span.start + self.offset, token.span.end + self.offset)
     }
 
-    fn emit_bytes(&mut self, op1: OpCode, op2: OpCode) {
-        self.chunk.write(op1, self.parser.previous.line);
-        self.chunk.write(op2, self.parser.previous.line);
+    /// Emits a jump with a placeholder offset and returns the offset of its operand for
+    /// [`Self::patch_jump`].
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_operand(op, usize::from(u16::MAX));
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, operand: usize) {
+        let jump = self.chunk().code.len() - operand - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            self.error(ErrorCode::LimitExceeded, "Too much code to jump over.");
+            return;
+        };
+        self.chunk().patch_jump(operand, jump);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        // The distance back covers the loop instruction itself, operand included.
+        let offset = self.chunk().code.len() - loop_start + 3;
+        if offset > usize::from(u16::MAX) {
+            self.error(ErrorCode::LimitExceeded, "Loop body too large.");
+        }
+        self.emit_operand(OpCode::Loop, offset);
     }
 
     fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit_operand(OpCode::GetLocal, 0);
+        } else {
+            self.emit_byte(OpCode::Nil);
+        }
         self.emit_byte(OpCode::Return);
     }
 
-    fn emit_constant(&mut self, constant: Constant<'src>) {
-        let index = self.chunk.add_constant(constant);
-        self.emit_byte(OpCode::Constant(index));
+    fn make_constant(&mut self, constant: Value) -> usize {
+        let index = self.chunk().add_constant(constant);
+        if index >= 1 << 24 {
+            self.error(ErrorCode::LimitExceeded, "Too many constants in one chunk.");
+            return 0;
+        }
+        index
+    }
+
+    fn emit_constant(&mut self, constant: Value) {
+        let index = self.make_constant(constant);
+        self.emit_operand(OpCode::Constant, index);
     }
 
     fn number(&mut self) {
-        let value: Double = self
-            .parser
-            .previous
-            .lexeme
-            .parse()
-            .expect("Lexeme cannot be parsed into value.");
-        self.emit_constant(Constant::Number(value));
+        let Ok(value) = self.parser.previous.lexeme.parse::<Double>() else {
+            self.error(ErrorCode::UnexpectedCharacter, "Invalid number literal.");
+            return;
+        };
+        self.emit_constant(Value::Number(value));
+        self.ast_node(self.ast_mark(), || format!("Number {value}"));
     }
 
     fn grouping(&mut self) {
+        let mark = self.ast_mark();
         self.expression();
         self.consume(TokenKind::RightParen, "Expect ')' after expression.");
+        self.ast_node(mark, || "Group".to_owned());
     }
 
     fn unary(&mut self) {
-        let operator_kind = self.parser.previous.kind;
+        let mark = self.ast_mark();
+        let operator = self.parser.previous;
+        let operand = self.chunk().mark();
         self.parse_precedence(Precedence::Unary);
-        match operator_kind {
-            TokenKind::Bang => self.emit_byte(OpCode::Not),
-            TokenKind::Minus => self.emit_byte(OpCode::Negate),
-            _ => (),
+        self.ast_node(mark, || format!("Unary {}", operator.lexeme));
+        let op = match operator.kind {
+            TokenKind::Bang => OpCode::Not,
+            TokenKind::Minus => OpCode::Negate,
+            _ => return,
+        };
+        let end = self.chunk().code.len();
+        match self
+            .literal_in(operand.code, end)
+            .and_then(|value| fold_unary(op, value))
+        {
+            Some(value) => self.emit_folded(operand, value, operator),
+            None => self.emit_byte_at(op, operator),
         }
     }
 
-    fn binary(&mut self) {
-        let operator_kind = self.parser.previous.kind;
-        let rule = Parser::rule(operator_kind);
+    /// Compiles the operator and right operand of a binary expression whose left operand was
+    /// compiled from `left` on.
+    fn binary(&mut self, left: Mark) {
+        let mark = self.ast_mark_infix();
+        let operator = self.parser.previous;
+        let rule = Parser::rule(operator.kind);
+        let right = self.chunk().code.len();
         self.parse_precedence(rule.precedence.strengthen());
-        match operator_kind {
-            TokenKind::Plus => self.emit_byte(OpCode::Add),
-            TokenKind::Minus => self.emit_byte(OpCode::Subtract),
-            TokenKind::Star => self.emit_byte(OpCode::Multiply),
-            TokenKind::Slash => self.emit_byte(OpCode::Divide),
-            TokenKind::BangEqual => self.emit_bytes(OpCode::Equal, OpCode::Not),
-            TokenKind::EqualEqual => self.emit_byte(OpCode::Equal),
-            TokenKind::Greater => self.emit_byte(OpCode::Greater),
-            TokenKind::GreaterEqual => self.emit_bytes(OpCode::Less, OpCode::Not),
-            TokenKind::Less => self.emit_byte(OpCode::Less),
-            TokenKind::LessEqual => self.emit_bytes(OpCode::Greater, OpCode::Not),
-            _ => (),
+        self.ast_node(mark, || format!("Binary {}", operator.lexeme));
+        let ops: &[OpCode] = match operator.kind {
+            TokenKind::Plus => &[OpCode::Add],
+            TokenKind::Minus => &[OpCode::Subtract],
+            TokenKind::Star => &[OpCode::Multiply],
+            TokenKind::Slash => &[OpCode::Divide],
+            TokenKind::BangEqual => &[OpCode::Equal, OpCode::Not],
+            TokenKind::EqualEqual => &[OpCode::Equal],
+            TokenKind::Greater => &[OpCode::Greater],
+            TokenKind::GreaterEqual => &[OpCode::Less, OpCode::Not],
+            TokenKind::Less => &[OpCode::Less],
+            TokenKind::LessEqual => &[OpCode::Greater, OpCode::Not],
+            _ => &[],
+        };
+        if let Some(value) = self.fold_binary(left, right, ops) {
+            self.emit_folded(left, value, operator);
+            return;
+        }
+        for &op in ops {
+            self.emit_byte_at(op, operator);
+        }
+    }
+
+    /// Runs `ops` at compile time if the code from `left` is a literal followed by another
+    /// literal starting at `right`. Gives up on anything that would be a runtime error, such as
+    /// `1 + "a"`, so that the error still happens when the script runs.
+    fn fold_binary(&mut self, left: Mark, right: usize, ops: &[OpCode]) -> Option<Value> {
+        let end = self.chunk().code.len();
+        let a = self.literal_in(left.code, right)?;
+        let b = self.literal_in(right, end)?;
+        let (&op, rest) = ops.split_first()?;
+        let mut value = match (op, a, b) {
+            (OpCode::Equal, a, b) => Value::Bool(a == b),
+            (_, Value::Number(a), Value::Number(b)) => match op {
+                OpCode::Add => Value::Number(a + b),
+                OpCode::Subtract => Value::Number(a - b),
+                OpCode::Multiply => Value::Number(a * b),
+                OpCode::Divide => Value::Number(a / b),
+                OpCode::Greater => Value::Bool(a > b),
+                OpCode::Less => Value::Bool(a < b),
+                _ => return None,
+            },
+            (OpCode::Add, Value::Object(a), Value::Object(b)) => {
+                let (ObjectType::String(a), ObjectType::String(b)) =
+                    (self.heap.get(a), self.heap.get(b))
+                else {
+                    return None;
+                };
+                let result = a.chars.clone() + &b.chars;
+                Value::Object(self.heap.intern(&result))
+            }
+            _ => return None,
+        };
+        for &op in rest {
+            value = fold_unary(op, value)?;
+        }
+        Some(value)
+    }
+
+    /// The value of the code from `start` to `end` if it is a single literal.
+    fn literal_in(&mut self, start: usize, end: usize) -> Option<Value> {
+        let chunk = self.chunk();
+        let op = OpCode::from_byte(*chunk.code.get(start)?)?;
+        if start + 1 + op.operand_width() != end {
+            return None;
+        }
+        match op {
+            OpCode::Constant | OpCode::ConstantLong => {
+                Some(chunk.constant(chunk.read_operand(start + 1, op.operand_width())))
+            }
+            OpCode::True => Some(Value::Bool(true)),
+            OpCode::False => Some(Value::Bool(false)),
+            OpCode::Nil => Some(Value::Nil),
+            _ => None,
+        }
+    }
+
+    /// Replaces the code from `start` on, and the constants it added, with a literal for
+    /// `value`.
+    fn emit_folded(&mut self, start: Mark, value: Value, operator: Token) {
+        self.chunk().rewind(start);
+        match value {
+            Value::Bool(true) => self.emit_byte_at(OpCode::True, operator),
+            Value::Bool(false) => self.emit_byte_at(OpCode::False, operator),
+            Value::Nil => self.emit_byte_at(OpCode::Nil, operator),
+            Value::Number(_) | Value::Object(_) => {
+                let index = self.make_constant(value);
+                self.emit_operand_at(OpCode::Constant, index, operator);
+            }
         }
     }
 
     fn literal(&mut self) {
+        let literal = self.parser.previous.lexeme;
+        self.ast_node(self.ast_mark(), || format!("Literal {literal}"));
         match self.parser.previous.kind {
             TokenKind::False => self.emit_byte(OpCode::False),
             TokenKind::Nil => self.emit_byte(OpCode::Nil),
@@ -330,36 +946,171 @@ impl<'src> Compiler<'src> {
     fn string(&mut self) {
         match self.parser.previous.kind {
             TokenKind::String => {
-                let str = self.parser.previous.lexeme.trim_matches('"');
-                self.emit_constant(Constant::String(str));
+                let lexeme = self.parser.previous.lexeme;
+                let str = self.heap.intern(lexeme.trim_matches('"'));
+                self.emit_constant(Value::Object(str));
+                self.ast_node(self.ast_mark(), || format!("String {lexeme}"));
+            }
+            _ => self.error(ErrorCode::ExpectedExpression, "Expect string constant."),
+        }
+    }
+
+    fn call(&mut self) {
+        let mark = self.ast_mark_infix();
+        let paren = self.parser.previous;
+        let arg_count = self.argument_list();
+        self.emit_operand_at(OpCode::Call, arg_count, paren);
+        self.ast_node(mark, || "Call".to_owned());
+    }
+
+    fn argument_list(&mut self) -> usize {
+        let mut arg_count = 0;
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == 255 {
+                    self.error(
+                        ErrorCode::LimitExceeded,
+                        "Can't have more than 255 arguments.",
+                    );
+                }
+                arg_count += 1;
+                if !self.match_token(TokenKind::Comma) {
+                    break;
+                }
             }
-            _ => self.error_at(&self.parser.previous, "Expect string constant."),
         }
+        self.consume(TokenKind::RightParen, "Expect ')' after arguments.");
+        arg_count
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        let mark = self.ast_mark_infix();
+        self.consume(TokenKind::Identifier, "Expect property name after '.'.");
+        let token = self.parser.previous;
+        let name = self.identifier_constant(token.lexeme);
+        if can_assign && self.match_token(TokenKind::Equal) {
+            self.expression();
+            self.emit_operand_at(OpCode::SetProperty, name, token);
+            self.ast_node(mark, || format!("Set {}", token.lexeme));
+        } else {
+            self.emit_operand_at(OpCode::GetProperty, name, token);
+            self.ast_node(mark, || format!("Get {}", token.lexeme));
+        }
+    }
+
+    fn and(&mut self) {
+        let mark = self.ast_mark_infix();
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::And);
+        self.patch_jump(end_jump);
+        self.ast_node(mark, || "Logical and".to_owned());
+    }
+
+    fn or(&mut self) {
+        let mark = self.ast_mark_infix();
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+        self.ast_node(mark, || "Logical or".to_owned());
     }
 
     fn variable(&mut self, can_assign: bool) {
-        let arg = self.identifier_constant(self.parser.previous.lexeme);
+        let mark = self.ast_mark();
+        let name = self.parser.previous;
+        self.named_variable(name, can_assign);
+        // Only an assignment records anything while resolving the name: the assigned value.
+        let assigned = self.ast_mark() > mark;
+        self.ast_node(mark, || {
+            let kind = if assigned { "Assign" } else { "Variable" };
+            format!("{kind} {}", name.lexeme)
+        });
+    }
+
+    fn super_(&mut self) {
+        match self.classes.last() {
+            None => self.error(
+                ErrorCode::SuperOutsideClass,
+                "Can't use 'super' outside of a class.",
+            ),
+            Some(class) if !class.has_superclass => {
+                self.error(
+                    ErrorCode::SuperWithoutSuperclass,
+                    "Can't use 'super' in a class with no superclass.",
+                );
+            }
+            Some(_) => (),
+        }
+        self.consume(TokenKind::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenKind::Identifier, "Expect superclass method name.");
+        let token = self.parser.previous;
+        let name = self.identifier_constant(token.lexeme);
+
+        self.named_variable(Token::synthetic("this"), false);
+        self.named_variable(Token::synthetic("super"), false);
+        self.emit_operand_at(OpCode::GetSuper, name, token);
+        self.ast_node(self.ast_mark(), || format!("Super {}", token.lexeme));
+    }
+
+    fn this(&mut self) {
+        if self.classes.is_empty() {
+            self.error(
+                ErrorCode::ThisOutsideClass,
+                "Can't use 'this' outside of a class.",
+            );
+            return;
+        }
+        self.named_variable(self.parser.previous, false);
+        self.ast_node(self.ast_mark(), || "This".to_owned());
+    }
+
+    fn named_variable(&mut self, name: Token<'src>, can_assign: bool) {
+        let current = self.functions.len() - 1;
+        let local = self.resolve_local(current, &name);
+        let upvalue = if local.is_none() {
+            self.resolve_upvalue(current, &name)
+        } else {
+            None
+        };
+        let (get_op, set_op, arg) = match (local, upvalue) {
+            (Some(slot), _) => (OpCode::GetLocal, OpCode::SetLocal, slot),
+            (None, Some(index)) => (OpCode::GetUpvalue, OpCode::SetUpvalue, index),
+            (None, None) => {
+                let arg = self.identifier_constant(name.lexeme);
+                (OpCode::GetGlobal, OpCode::SetGlobal, arg)
+            }
+        };
         if can_assign && self.match_token(TokenKind::Equal) {
             self.expression();
-            self.emit_byte(OpCode::SetGlobal(arg));
+            self.emit_operand_at(set_op, arg, name);
         } else {
-            self.emit_byte(OpCode::GetGlobal(arg));
+            self.emit_operand_at(get_op, arg, name);
         }
     }
 
     fn parse_precedence(&mut self, precedence: Precedence) {
         let can_assign = precedence <= Precedence::Assignment;
         self.advance();
+        let start = self.chunk().mark();
         let prefix_rule = Parser::rule(self.parser.previous.kind).prefix;
         match prefix_rule {
             Some(FunctionRepr::Grouping) => self.grouping(),
             Some(FunctionRepr::Unary) => self.unary(),
-            Some(FunctionRepr::Binary) => self.binary(),
+            Some(FunctionRepr::Binary) => self.binary(start),
             Some(FunctionRepr::Number) => self.number(),
             Some(FunctionRepr::Literal) => self.literal(),
             Some(FunctionRepr::String) => self.string(),
             Some(FunctionRepr::Variable) => self.variable(can_assign),
-            None => self.error_at(&self.parser.previous, "Expect prefix expression."),
+            Some(FunctionRepr::This) => self.this(),
+            Some(FunctionRepr::Super) => self.super_(),
+            Some(FunctionRepr::And | FunctionRepr::Or | FunctionRepr::Call | FunctionRepr::Dot)
+            | None => {
+                self.error(ErrorCode::ExpectedExpression, "Expect prefix expression.");
+            }
         }
 
         while precedence <= Parser::rule(self.parser.current.kind).precedence {
@@ -368,43 +1119,198 @@ impl<'src> Compiler<'src> {
             match infix_rule {
                 Some(FunctionRepr::Grouping) => self.grouping(),
                 Some(FunctionRepr::Unary) => self.unary(),
-                Some(FunctionRepr::Binary) => self.binary(),
+                Some(FunctionRepr::Binary) => self.binary(start),
                 Some(FunctionRepr::Number) => self.number(),
-                _ => self.error_at(&self.parser.previous, "Expect infix expression."),
+                Some(FunctionRepr::And) => self.and(),
+                Some(FunctionRepr::Or) => self.or(),
+                Some(FunctionRepr::Call) => self.call(),
+                Some(FunctionRepr::Dot) => self.dot(can_assign),
+                _ => self.error(ErrorCode::ExpectedExpression, "Expect infix expression."),
             }
         }
 
         if can_assign && self.match_token(TokenKind::Equal) {
-            self.error_at(&self.parser.previous, "Invalid assignment target.");
+            self.error_with_help(
+                ErrorCode::InvalidAssignmentTarget,
+                "Invalid assignment target.",
+                "did you mean `==`?",
+            );
         }
     }
 
     fn identifier_constant(&mut self, name: &'src str) -> usize {
-        self.chunk.add_constant(Constant::String(name))
+        let name = self.heap.intern(name);
+        self.make_constant(Value::Object(name))
+    }
+
+    /// Returns the slot of the innermost local named `name` in the function at `function`, or
+    /// `None` if it is not a local of that function.
+    fn resolve_local(&mut self, function: usize, name: &Token) -> Option<usize> {
+        let (slot, local) = self.functions[function]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.lexeme == name.lexeme)?;
+        if local.depth.is_none() {
+            self.error_with_note(
+                ErrorCode::LocalInOwnInitializer,
+                "Can't read local variable in its own initializer.",
+                "a local variable is not in scope until its initializer has run",
+            );
+        }
+        Some(slot)
+    }
+
+    /// Returns the index of the upvalue through which the function at `function` can reach a
+    /// local named `name` of an enclosing function, adding upvalues along the way as needed.
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Option<usize> {
+        let enclosing = function.checked_sub(1)?;
+        if let Some(local) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[local].is_captured = true;
+            return Some(self.add_upvalue(function, local, true));
+        }
+        let upvalue = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: usize, is_local: bool) -> usize {
+        let upvalue = UpvalueRef { is_local, index };
+        let upvalues = &mut self.functions[function].function.upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing;
+        }
+        if upvalues.len() == 256 {
+            self.error(
+                ErrorCode::LimitExceeded,
+                "Too many closure variables in function.",
+            );
+            return 0;
+        }
+        upvalues.push(upvalue);
+        upvalues.len() - 1
+    }
+
+    fn add_local(&mut self, name: Token<'src>) {
+        if self.state().locals.len() > usize::from(u8::MAX) {
+            self.error(
+                ErrorCode::LimitExceeded,
+                "Too many local variables in function.",
+            );
+            return;
+        }
+        self.state().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    fn declare_variable(&mut self) {
+        let scope_depth = self.state().scope_depth;
+        if scope_depth == 0 {
+            return;
+        }
+        let name = self.parser.previous;
+        let already_declared = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= scope_depth))
+            .any(|local| local.name.lexeme == name.lexeme);
+        if already_declared {
+            self.error_with_help(
+                ErrorCode::DuplicateLocal,
+                "Already a variable with this name in this scope.",
+                "assign to the existing variable, or pick another name",
+            );
+        }
+        self.add_local(name);
     }
 
     fn parse_variable(&mut self, err_msg: &str) -> usize {
         self.consume(TokenKind::Identifier, err_msg);
+        self.declare_variable();
+        if self.state().scope_depth > 0 {
+            return 0;
+        }
         self.identifier_constant(self.parser.previous.lexeme)
     }
 
-    fn define_variable(&mut self, global: usize) {
-        self.emit_byte(OpCode::DefineGlobal(global));
+    fn mark_initialized(&mut self) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(state.scope_depth);
+        }
     }
 
-    fn error_at(&self, token: &Token, err_msg: &str) {
-        if self.parser.panic_mode {
+    fn define_variable(&mut self, global: usize) {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
             return;
         }
-        print!("[line {}] Error", token.line);
-        if matches!(token.kind, TokenKind::Eof) {
-            print!(" at end");
-        } else if matches!(token.kind, TokenKind::Error) {
-            // Do nothing
-        } else {
-            print!(" at {}", token.lexeme);
+        self.emit_operand(OpCode::DefineGlobal, global);
+    }
+
+    fn error(&mut self, code: ErrorCode, err_msg: &str) {
+        self.error_at(self.parser.previous, code, err_msg);
+    }
+
+    fn error_with_note(&mut self, code: ErrorCode, err_msg: &str, note: &str) {
+        if let Some(diagnostic) = self.report(self.parser.previous, code, err_msg) {
+            diagnostic.notes.push(note.to_owned());
+        }
+    }
+
+    fn error_with_help(&mut self, code: ErrorCode, err_msg: &str, help: &str) {
+        if let Some(diagnostic) = self.report(self.parser.previous, code, err_msg) {
+            diagnostic.help = Some(help.to_owned());
         }
+    }
+
+    fn error_at_current(&mut self, code: ErrorCode, err_msg: &str) {
+        self.error_at(self.parser.current, code, err_msg);
+    }
+
+    fn error_at(&mut self, token: Token, code: ErrorCode, err_msg: &str) {
+        self.report(token, code, err_msg);
+    }
+
+    /// Records an error at `token` and returns it so notes or help can be attached, or returns
+    /// `None` if the error is suppressed because the parser is still recovering.
+    fn report(&mut self, token: Token, code: ErrorCode, err_msg: &str) -> Option<&mut Diagnostic> {
+        if self.parser.panic_mode {
+            return None;
+        }
+        self.parser.panic_mode = true;
+        let lexeme = match token.kind {
+            TokenKind::Error => &self.source[token.span.start..token.span.end],
+            _ => token.lexeme,
+        };
+        self.parser.diagnostics.push(Diagnostic {
+            code,
+            message: err_msg.to_owned(),
+            kind: token.kind,
+            lexeme: lexeme.to_owned(),
+            line: token.line + self.lines,
+            column: token.column,
+            span: self.span(&token),
+            notes: Vec::new(),
+            help: None,
+        });
+        self.parser.diagnostics.last_mut()
+    }
+}
 
-        println!(": {err_msg}");
+/// Runs the unary `op` on a literal at compile time, unless that would be a runtime error.
+const fn fold_unary(op: OpCode, value: Value) -> Option<Value> {
+    match (op, value) {
+        (OpCode::Not, value) => Some(Value::Bool(is_falsey(value))),
+        (OpCode::Negate, Value::Number(number)) => Some(Value::Number(-number)),
+        _ => None,
     }
 }
@@ -0,0 +1,131 @@
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, OpCode};
+use crate::heap::{Heap, ObjRef};
+use crate::value::{ObjectType, Value};
+
+/// Disassembles `function` followed by every function nested in it, depth first, in the
+/// format of clox's `disassembleChunk`:
+///
+/// ```text
+/// == <script> == 1 constant
+/// 0000    1 Constant            0 '1'
+/// 0001    | Print
+/// 0002    2 JumpIfFalse         2 -> 5
+/// ```
+pub fn disassemble(heap: &Heap, function: ObjRef) -> String {
+    let mut out = String::new();
+    let mut pending = vec![function];
+    while let Some(function) = pending.pop() {
+        let ObjectType::Function(function) = heap.get(function) else {
+            continue;
+        };
+        let name = function.name.map_or_else(
+            || "<script>".to_owned(),
+            |name| heap.string(name).to_owned(),
+        );
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&disassemble_chunk(heap, &function.chunk, &name));
+        // Reversed so that nested functions come out in the order they were declared.
+        for &constant in function.chunk.constants().iter().rev() {
+            if let Value::Object(object) = constant {
+                if let ObjectType::Function(_) = heap.get(object) {
+                    pending.push(object);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Disassembles the instructions of a single chunk under a `== name ==` header, which also
+/// gives the size of its constant pool.
+pub fn disassemble_chunk(heap: &Heap, chunk: &Chunk, name: &str) -> String {
+    let constants = chunk.constants().len();
+    let plural = if constants == 1 { "" } else { "s" };
+    let mut out = format!("== {name} == {constants} constant{plural}\n");
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        out.push_str(&disassemble_instruction(heap, chunk, offset));
+        out.push('\n');
+        offset = next_instruction(chunk, offset);
+    }
+    out
+}
+
+/// Offset of the instruction after the one at `offset`.
+fn next_instruction(chunk: &Chunk, offset: usize) -> usize {
+    OpCode::from_byte(chunk.code[offset]).map_or(offset + 1, |op| offset + 1 + op.operand_width())
+}
+
+/// Disassembles the instruction at `offset` as a single line, or several for a closure, which
+/// lists the variables it captures. The result has no trailing newline.
+pub fn disassemble_instruction(heap: &Heap, chunk: &Chunk, offset: usize) -> String {
+    let mut out = format!("{offset:04} ");
+    let line = chunk.line(offset);
+    if offset > 0 && chunk.line(offset - 1) == line {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{line:4} ");
+    }
+    let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+        let _ = write!(out, "Unknown opcode {}", chunk.code[offset]);
+        return out;
+    };
+    let name = format!("{op:?}");
+    let operand = chunk.read_operand(offset + 1, op.operand_width());
+    // Jumps are relative to the end of the instruction.
+    let next = offset + 1 + op.operand_width();
+    match op {
+        OpCode::Constant
+        | OpCode::ConstantLong
+        | OpCode::GetGlobal
+        | OpCode::GetGlobalLong
+        | OpCode::DefineGlobal
+        | OpCode::DefineGlobalLong
+        | OpCode::SetGlobal
+        | OpCode::SetGlobalLong
+        | OpCode::GetProperty
+        | OpCode::GetPropertyLong
+        | OpCode::SetProperty
+        | OpCode::SetPropertyLong
+        | OpCode::GetSuper
+        | OpCode::GetSuperLong
+        | OpCode::Class
+        | OpCode::ClassLong
+        | OpCode::Method
+        | OpCode::MethodLong
+        | OpCode::AddConstant
+        | OpCode::AddConstantLong => {
+            let value = heap.display(chunk.constant(operand));
+            let _ = write!(out, "{name:<16} {operand:4} '{value}'");
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            let _ = write!(out, "{name:<16} {operand:4}");
+        }
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            let _ = write!(out, "{name:<16} {operand:4} -> {}", next + operand);
+        }
+        OpCode::Loop => {
+            let _ = write!(out, "{name:<16} {operand:4} -> {}", next - operand);
+        }
+        OpCode::Closure | OpCode::ClosureLong => {
+            let function = chunk.constant(operand);
+            let _ = write!(out, "{name:<16} {operand:4} '{}'", heap.display(function));
+            if let Value::Object(function) = function {
+                for upvalue in &heap.function(function).upvalues {
+                    let kind = if upvalue.is_local { "local" } else { "upvalue" };
+                    let _ = write!(out, "\n{offset:04}    |{:<18}{kind} {}", "", upvalue.index);
+                }
+            }
+        }
+        _ => out.push_str(&name),
+    }
+    out
+}
@@ -1,22 +1,137 @@
 use std::io;
 
+use crate::bytecode::LoadError;
+use crate::codes::ErrorCode;
+use crate::scanner::{Span, TokenKind};
+use crate::value::{Column, Line};
+use crate::verifier::VerifyError;
+
 #[derive(Debug)]
 pub enum Error {
-    Compiler,
-    Runtime,
+    /// Every problem the compiler found. Code that fails to compile is never run.
+    Compiler(Vec<Diagnostic>),
+    Runtime(RuntimeError),
+    /// The VM was handed bytecode that breaks an invariant the compiler guarantees, such as
+    /// popping from an empty stack.
+    InvalidBytecode,
+    /// A file that should hold compiled bytecode could not be loaded.
+    Load(LoadError),
+    /// Loaded bytecode failed verification, so it was not run.
+    Verify(VerifyError),
+    /// Writing `--trace` output to the file or stream called `name` failed.
+    Trace {
+        name: String,
+        error: io::Error,
+    },
     IO(io::Error),
 }
 
+/// A compile error, located at the token the compiler was looking at when it found it. Its
+/// `Display` matches the book's `[line N] Error at 'x': message`.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    pub kind: TokenKind,
+    /// The token's source text; for scanner errors, the text the scanner gave up on.
+    pub lexeme: String,
+    pub line: Line,
+    pub column: Column,
+    pub span: Span,
+    /// Extra context shown under the source snippet.
+    pub notes: Vec<String>,
+    /// A suggestion for fixing the error.
+    pub help: Option<String>,
+}
+
+/// An error raised while executing bytecode. Its `Display` matches the book: the message
+/// followed by one `[line N] in f()` line per active call, innermost first.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Active calls, innermost first, so the first frame holds the line that failed.
+    pub backtrace: Vec<TraceFrame>,
+}
+
+/// One active call at the time of a [`RuntimeError`].
+#[derive(Debug)]
+pub struct TraceFrame {
+    /// Line of the instruction the call was executing.
+    pub line: Line,
+    /// Column and span of the token that instruction was compiled from.
+    pub column: Column,
+    pub span: Span,
+    /// `None` for top-level code.
+    pub function: Option<String>,
+}
+
+impl Error {
+    /// Process exit status for the error, following sysexits.h as clox does.
+    pub const fn exit_code(&self) -> i32 {
+        match self {
+            // EX_DATAERR
+            Self::Compiler(_) | Self::Load(_) | Self::Verify(_) => 65,
+            // EX_SOFTWARE
+            Self::Runtime(_) | Self::InvalidBytecode => 70,
+            // EX_IOERR
+            Self::Trace { .. } | Self::IO(_) => 74,
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Compiler => write!(f, "Compiler error."),
-            Self::Runtime => write!(f, "Runtime error"),
+            Self::Compiler(diagnostics) => {
+                let mut separator = "";
+                for diagnostic in diagnostics {
+                    write!(f, "{separator}{diagnostic}")?;
+                    separator = "\n";
+                }
+                Ok(())
+            }
+            Self::Runtime(err) => write!(f, "{err}"),
+            Self::InvalidBytecode => write!(f, "Invalid bytecode."),
+            Self::Load(err) => write!(f, "{err}"),
+            Self::Verify(err) => write!(f, "{err}"),
+            Self::Trace { name, error } => write!(f, "couldn't write trace to {name}: {error}"),
             Self::IO(err) => write!(f, "{err}"),
         }
     }
 }
 
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Error", self.line)?;
+        match self.kind {
+            TokenKind::Eof => write!(f, " at end")?,
+            TokenKind::Error => (),
+            _ => write!(f, " at '{}'", self.lexeme)?,
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in &self.backtrace {
+            write!(f, "\n{frame}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "[line {}] in {name}()", self.line),
+            None => write!(f, "[line {}] in script", self.line),
+        }
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         None
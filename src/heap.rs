@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::mem::size_of;
+
+use crate::object::{hash_string, Class, Closure, Function, LoxString, StringKey, Upvalue};
+use crate::value::{ObjectType, Value};
+
+/// Bytes allocated before the first collection is triggered.
+const INITIAL_NEXT_GC: usize = 1024 * 1024;
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+/// Handle to an object owned by a [`Heap`]. Handles are only meaningful for the heap that
+/// allocated them and stay valid for as long as the object is reachable from a GC root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(u32);
+
+impl ObjRef {
+    const fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Owner of every object created by the compiler and the VM, reclaimed with a mark-and-sweep
+/// collector. The heap does not know its roots: whoever calls [`Heap::collect`] must pass them.
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Option<ObjectType>>,
+    marks: Vec<bool>,
+    free: Vec<u32>,
+    gray: Vec<ObjRef>,
+    /// Every live string, bucketed by hash. The table does not keep strings alive: sweeping a
+    /// string also removes it from here.
+    strings: HashMap<u32, Vec<ObjRef>>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    stress: bool,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            gray: Vec::new(),
+            strings: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: INITIAL_NEXT_GC,
+            stress: false,
+        }
+    }
+
+    /// Makes [`Heap::should_collect`] return `true` on every allocation, which flushes out
+    /// objects that are reachable but were never rooted.
+    pub const fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub const fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn alloc(&mut self, object: ObjectType) -> ObjRef {
+        self.bytes_allocated += object.size();
+        if let Some(index) = self.free.pop() {
+            self.objects[index as usize] = Some(object);
+            return ObjRef(index);
+        }
+        let index = u32::try_from(self.objects.len()).expect("Heap exceeded u32::MAX objects.");
+        self.objects.push(Some(object));
+        self.marks.push(false);
+        ObjRef(index)
+    }
+
+    /// Returns the one string object holding `chars`, allocating it on first use. Since strings
+    /// are never duplicated, two strings are equal exactly when their handles are.
+    pub fn intern(&mut self, chars: &str) -> ObjRef {
+        let hash = hash_string(chars);
+        if let Some(bucket) = self.strings.get(&hash) {
+            if let Some(&string) = bucket.iter().find(|&&string| self.string(string) == chars) {
+                return string;
+            }
+        }
+        let string = self.alloc(ObjectType::String(LoxString::new(chars.to_owned())));
+        self.strings.entry(hash).or_default().push(string);
+        string
+    }
+
+    pub fn get(&self, object: ObjRef) -> &ObjectType {
+        self.objects[object.index()]
+            .as_ref()
+            .expect("Object handle used after it was freed.")
+    }
+
+    pub fn get_mut(&mut self, object: ObjRef) -> &mut ObjectType {
+        self.objects[object.index()]
+            .as_mut()
+            .expect("Object handle used after it was freed.")
+    }
+
+    pub fn string(&self, object: ObjRef) -> &str {
+        match self.get(object) {
+            ObjectType::String(string) => &string.chars,
+            other => panic!("Expected string, found {other:?}."),
+        }
+    }
+
+    /// The string `object` as a table key.
+    pub fn key(&self, object: ObjRef) -> StringKey {
+        match self.get(object) {
+            ObjectType::String(string) => StringKey::new(object, string.hash),
+            other => panic!("Expected string, found {other:?}."),
+        }
+    }
+
+    pub fn function(&self, object: ObjRef) -> &Function {
+        match self.get(object) {
+            ObjectType::Function(function) => function,
+            other => panic!("Expected function, found {other:?}."),
+        }
+    }
+
+    pub fn closure(&self, object: ObjRef) -> &Closure {
+        match self.get(object) {
+            ObjectType::Closure(closure) => closure,
+            other => panic!("Expected closure, found {other:?}."),
+        }
+    }
+
+    pub fn closure_mut(&mut self, object: ObjRef) -> &mut Closure {
+        match self.get_mut(object) {
+            ObjectType::Closure(closure) => closure,
+            other => panic!("Expected closure, found {other:?}."),
+        }
+    }
+
+    pub fn upvalue(&self, object: ObjRef) -> &Upvalue {
+        match self.get(object) {
+            ObjectType::Upvalue(upvalue) => upvalue,
+            other => panic!("Expected upvalue, found {other:?}."),
+        }
+    }
+
+    pub fn upvalue_mut(&mut self, object: ObjRef) -> &mut Upvalue {
+        match self.get_mut(object) {
+            ObjectType::Upvalue(upvalue) => upvalue,
+            other => panic!("Expected upvalue, found {other:?}."),
+        }
+    }
+
+    pub fn class(&self, object: ObjRef) -> &Class {
+        match self.get(object) {
+            ObjectType::Class(class) => class,
+            other => panic!("Expected class, found {other:?}."),
+        }
+    }
+
+    pub fn class_mut(&mut self, object: ObjRef) -> &mut Class {
+        match self.get_mut(object) {
+            ObjectType::Class(class) => class,
+            other => panic!("Expected class, found {other:?}."),
+        }
+    }
+
+    /// Returns a wrapper that formats `value` the way Lox's `print` does.
+    pub const fn display(&self, value: Value) -> ValueDisplay<'_> {
+        ValueDisplay { heap: self, value }
+    }
+
+    /// Frees every object that is not reachable from `roots`.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Value>) {
+        for root in roots {
+            self.mark_value(root);
+        }
+        self.trace_references();
+        self.sweep();
+        self.next_gc = self
+            .bytes_allocated
+            .max(INITIAL_NEXT_GC / GC_HEAP_GROW_FACTOR)
+            * GC_HEAP_GROW_FACTOR;
+    }
+
+    fn mark_value(&mut self, value: Value) {
+        if let Value::Object(object) = value {
+            mark(object, &mut self.marks, &mut self.gray);
+        }
+    }
+
+    /// Blackens gray objects until none are left, graying the objects each one references.
+    fn trace_references(&mut self) {
+        while let Some(object) = self.gray.pop() {
+            let Some(kind) = &self.objects[object.index()] else {
+                continue;
+            };
+            let marks = &mut self.marks;
+            let gray = &mut self.gray;
+            let mut mark_value = |value: Value| {
+                if let Value::Object(object) = value {
+                    mark(object, marks, gray);
+                }
+            };
+            match kind {
+                ObjectType::Function(function) => {
+                    if let Some(name) = function.name {
+                        mark_value(Value::Object(name));
+                    }
+                    for &constant in function.chunk.constants() {
+                        mark_value(constant);
+                    }
+                }
+                ObjectType::Closure(closure) => {
+                    mark_value(Value::Object(closure.function));
+                    for &upvalue in &closure.upvalues {
+                        mark_value(Value::Object(upvalue));
+                    }
+                }
+                ObjectType::Upvalue(Upvalue::Closed(value)) => mark_value(*value),
+                ObjectType::String(_) | ObjectType::Upvalue(Upvalue::Open(_)) => (),
+                ObjectType::Class(class) => {
+                    mark_value(Value::Object(class.name));
+                    for &method in class.methods.values() {
+                        mark_value(Value::Object(method));
+                    }
+                }
+                ObjectType::Instance(instance) => {
+                    mark_value(Value::Object(instance.class));
+                    for &field in instance.fields.values() {
+                        mark_value(field);
+                    }
+                }
+                ObjectType::BoundMethod(bound) => {
+                    mark_value(bound.receiver);
+                    mark_value(Value::Object(bound.method));
+                }
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        self.bytes_allocated = 0;
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            let Some(object) = slot else {
+                continue;
+            };
+            if self.marks[index] {
+                self.marks[index] = false;
+                self.bytes_allocated += object.size();
+            } else {
+                if let ObjectType::String(string) = object {
+                    forget_string(&mut self.strings, string.hash, index);
+                }
+                *slot = None;
+                self.free
+                    .push(u32::try_from(index).expect("Heap exceeded u32::MAX objects."));
+            }
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn mark(object: ObjRef, marks: &mut [bool], gray: &mut Vec<ObjRef>) {
+    if !marks[object.index()] {
+        marks[object.index()] = true;
+        gray.push(object);
+    }
+}
+
+fn forget_string(strings: &mut HashMap<u32, Vec<ObjRef>>, hash: u32, index: usize) {
+    if let Some(bucket) = strings.get_mut(&hash) {
+        bucket.retain(|string| string.index() != index);
+        if bucket.is_empty() {
+            strings.remove(&hash);
+        }
+    }
+}
+
+impl ObjectType {
+    /// Approximate number of bytes owned by the object, used to pace collections.
+    fn size(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                Self::String(string) => string.chars.capacity(),
+                Self::Function(function) => function.chunk.size(),
+                Self::Closure(closure) => closure.upvalues.capacity() * size_of::<ObjRef>(),
+                Self::Upvalue(_) | Self::BoundMethod(_) => 0,
+                Self::Class(class) => class.methods.capacity() * size_of::<(StringKey, ObjRef)>(),
+                Self::Instance(instance) => {
+                    instance.fields.capacity() * size_of::<(StringKey, Value)>()
+                }
+            }
+    }
+}
+
+pub struct ValueDisplay<'heap> {
+    heap: &'heap Heap,
+    value: Value,
+}
+
+impl ValueDisplay<'_> {
+    fn object(&self, f: &mut std::fmt::Formatter<'_>, object: ObjRef) -> std::fmt::Result {
+        match self.heap.get(object) {
+            ObjectType::String(string) => write!(f, "{}", string.chars),
+            ObjectType::Function(function) => match function.name {
+                Some(name) => write!(f, "<fn {}>", self.heap.string(name)),
+                None => write!(f, "<script>"),
+            },
+            ObjectType::Closure(closure) => self.object(f, closure.function),
+            ObjectType::Upvalue(_) => write!(f, "upvalue"),
+            ObjectType::Class(class) => write!(f, "{}", self.heap.string(class.name)),
+            ObjectType::Instance(instance) => {
+                let class = self.heap.class(instance.class);
+                write!(f, "{} instance", self.heap.string(class.name))
+            }
+            ObjectType::BoundMethod(bound) => self.object(f, bound.method),
+        }
+    }
+}
+
+impl Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            Value::Bool(bool) => write!(f, "{bool}"),
+            Value::Nil => write!(f, "nil"),
+            Value::Number(num) => write!(f, "{num}"),
+            Value::Object(object) => self.object(f, object),
+        }
+    }
+}
@@ -1,36 +1,238 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 // #![allow(dead_code)]
 
+mod ast;
+mod bytecode;
 mod chunk;
+mod codes;
 mod compiler;
+mod disassembler;
 mod error;
+mod heap;
+mod object;
+mod optimizer;
+mod render;
+mod repl;
 mod scanner;
 mod value;
+mod verifier;
 mod vm;
 
 use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
 
 use vm::VM;
 
-use crate::error::RloxResult;
+use crate::codes::ErrorCode;
+use crate::error::{Error, RloxResult};
+use crate::render::{ColorChoice, ErrorFormat, Renderer};
+use crate::scanner::{Scanner, TokenKind};
+
+const USAGE: &str = "Usage: rlox [-O0|-O1] [--gc-stress] [--trace] [--trace-out <file>] \
+                     [--color=never|always|auto] [--error-format=human|json] [[run] path]
+       rlox --tokens|--ast|--disassemble [-O0|-O1] <path>
+       rlox compile [-O0|-O1] <path> [-o <file.loxc>]
+       rlox --explain <code>";
 
 fn main() {
-    // TODO: Add back REPL support
-    let mut args = env::args();
-    if args.len() == 2 {
-        args.next();
-        match run_file(args.next().unwrap()) {
-            Ok(()) => {}
-            Err(e) => println!("Error: {e}"),
+    let mut optimize = true;
+    let mut gc_stress = false;
+    let mut mode = Mode::Run;
+    let mut trace = false;
+    let mut trace_out = None;
+    let mut color = ColorChoice::Auto;
+    let mut json = false;
+    let mut path = None;
+    let mut output = None;
+    let mut args = env::args().skip(1).peekable();
+    // `run` is the default, so it is accepted but changes nothing.
+    let compile = args.next_if(|arg| arg == "compile").is_some();
+    if !compile {
+        args.next_if(|arg| arg == "run");
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" if compile => output = Some(args.next().unwrap_or_else(|| usage())),
+            "--explain" => explain(&args.next().unwrap_or_else(|| usage())),
+            "-O0" => optimize = false,
+            "-O1" => optimize = true,
+            "--gc-stress" => gc_stress = true,
+            "--tokens" => mode = Mode::Tokens,
+            "--ast" => mode = Mode::Ast,
+            "--disassemble" => mode = Mode::Disassemble,
+            "--trace" => trace = true,
+            "--trace-out" => trace_out = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--color=") => {
+                color = ColorChoice::parse(&arg["--color=".len()..]).unwrap_or_else(|| usage());
+            }
+            _ if arg.starts_with("--error-format=") => {
+                json = match &arg["--error-format=".len()..] {
+                    "human" => false,
+                    "json" => true,
+                    _ => usage(),
+                };
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
         }
+    }
+    let format = if json {
+        ErrorFormat::Json
     } else {
-        eprintln!("Usage: rlox [path]");
+        ErrorFormat::Human {
+            color: color.enabled(),
+        }
+    };
+    let mut vm = VM::new();
+    vm.set_optimize(optimize);
+    vm.set_gc_stress(gc_stress);
+    if let Some(file) = trace_out {
+        match File::create(&file) {
+            Ok(out) => vm.set_trace(&file, Box::new(BufWriter::new(out))),
+            Err(e) => {
+                eprintln!("error: couldn't create {file}: {e}");
+                std::process::exit(Error::from(e).exit_code());
+            }
+        }
+    } else if trace {
+        vm.set_trace("stdout", Box::new(io::stdout()));
+    }
+    // These report their own errors, since only they have the source to render them against.
+    let result = match path {
+        Some(path) if compile && mode == Mode::Run => compile_file(&mut vm, &path, output, format),
+        Some(_) | None if compile => usage(),
+        Some(path) => run_file(&mut vm, &path, format, mode),
+        None if mode != Mode::Run => usage(),
+        None => repl::run(&mut vm, format),
+    };
+    if let Err(e) = result {
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Prints the long description of the error code `code` and exits.
+fn explain(code: &str) -> ! {
+    let Some(error) = ErrorCode::parse(code) else {
+        eprintln!("error: `{code}` is not an rlox error code");
         std::process::exit(64);
+    };
+    print!("{}", error.explanation());
+    std::process::exit(0);
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(64);
+}
+
+/// What to do with the script named on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Run,
+    /// Print the tokens the scanner produces.
+    Tokens,
+    /// Print the structure the compiler parsed.
+    Ast,
+    /// Print the compiled bytecode.
+    Disassemble,
+}
+
+/// Runs or inspects the script at `path`, which may be source or compiled bytecode.
+fn run_file(vm: &mut VM, path: &str, format: ErrorFormat, mode: Mode) -> RloxResult {
+    let bytes = read_file(path, format)?;
+    if bytecode::is_bytecode(&bytes) {
+        if mode != Mode::Run {
+            eprintln!("error: {path} is compiled bytecode; inspecting it needs the source");
+            std::process::exit(64);
+        }
+        if matches!(format, ErrorFormat::Human { .. }) {
+            warn_if_stale(path, &bytes);
+        }
+        let result = vm.run_bytecode(&bytes);
+        if let Err(e) = &result {
+            Renderer::new(path, "", format).report(e);
+        }
+        return result;
+    }
+    let source = source_text(path, bytes, format)?;
+    let result = match mode {
+        Mode::Run => vm.interpret(&source),
+        Mode::Tokens => {
+            print_tokens(&source);
+            Ok(())
+        }
+        Mode::Ast => vm.ast(&source).map(|tree| print!("{tree}")),
+        Mode::Disassemble => vm.disassemble(&source).map(|listing| print!("{listing}")),
+    };
+    if let Err(e) = &result {
+        Renderer::new(path, &source, format).report(e);
     }
+    result
 }
 
-fn run_file(path: String) -> RloxResult {
-    let mut vm = VM::new();
-    let contents = std::fs::read_to_string(path)?;
-    vm.interpret(&contents)
+/// Warns when the source next to the bytecode at `path` has changed since it was compiled.
+fn warn_if_stale(path: &str, bytes: &[u8]) {
+    let source = Path::new(path).with_extension("lox");
+    if source == Path::new(path) {
+        return;
+    }
+    if let Ok(text) = std::fs::read_to_string(&source) {
+        if !bytecode::compiled_from(bytes, &text) {
+            eprintln!(
+                "warning: {} has changed since {path} was compiled from it",
+                source.display()
+            );
+        }
+    }
+}
+
+/// Prints every token in `source`, errors included, up to and including the end of file.
+fn print_tokens(source: &str) {
+    let mut scanner = Scanner::new();
+    scanner.update_source(source);
+    loop {
+        let token = scanner.scan_token();
+        println!("{token}");
+        if token.kind == TokenKind::Eof {
+            break;
+        }
+    }
+}
+
+/// Compiles the script at `path` to bytecode in `output`, by default `path` with a `.loxc`
+/// extension.
+fn compile_file(
+    vm: &mut VM,
+    path: &str,
+    output: Option<String>,
+    format: ErrorFormat,
+) -> RloxResult {
+    let bytes = read_file(path, format)?;
+    let source = source_text(path, bytes, format)?;
+    let bytecode = vm.compile_to_bytecode(&source).inspect_err(|e| {
+        Renderer::new(path, &source, format).report(e);
+    })?;
+    let output = output.map_or_else(|| Path::new(path).with_extension("loxc"), PathBuf::from);
+    std::fs::write(&output, bytecode).map_err(|e| {
+        eprintln!("error: couldn't write {}: {e}", output.display());
+        Error::from(e)
+    })
+}
+
+fn read_file(path: &str, format: ErrorFormat) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| {
+        let e = Error::from(e);
+        Renderer::new(path, "", format).report(&e);
+        e
+    })
+}
+
+fn source_text(path: &str, bytes: Vec<u8>, format: ErrorFormat) -> Result<String, Error> {
+    String::from_utf8(bytes).map_err(|e| {
+        let e = Error::from(io::Error::new(io::ErrorKind::InvalidData, e));
+        Renderer::new(path, "", format).report(&e);
+        e
+    })
 }
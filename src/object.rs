@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+
+use crate::chunk::Chunk;
+use crate::heap::ObjRef;
+use crate::value::Value;
+
+/// An immutable string with its hash computed up front, so interning it never rehashes.
+#[derive(Debug)]
+pub struct LoxString {
+    pub chars: String,
+    pub hash: u32,
+}
+
+impl LoxString {
+    pub fn new(chars: String) -> Self {
+        let hash = hash_string(&chars);
+        Self { chars, hash }
+    }
+}
+
+/// 32-bit FNV-1a, the same hash clox uses for its string table.
+pub fn hash_string(chars: &str) -> u32 {
+    chars.bytes().fold(2_166_136_261, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(16_777_619)
+    })
+}
+
+/// An interned string as the key of a [`Table`]. Interned strings are equal only when they are
+/// the same object, so keys compare by handle and hash with the hash the string was interned
+/// with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StringKey {
+    pub string: ObjRef,
+    hash: u32,
+}
+
+impl StringKey {
+    pub const fn new(string: ObjRef, hash: u32) -> Self {
+        Self { string, hash }
+    }
+}
+
+impl Hash for StringKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.hash);
+    }
+}
+
+/// Passes the stored hash of a [`StringKey`] through rather than hashing it again.
+#[derive(Debug, Default)]
+pub struct StringKeyHasher(u64);
+
+impl Hasher for StringKeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, hash: u32) {
+        // Repeated into the high bits, which `HashMap` also uses to tell entries apart.
+        self.0 = (u64::from(hash) << 32) | u64::from(hash);
+    }
+}
+
+/// A hash table keyed by interned strings, for globals, fields and methods.
+pub type Table<V> = HashMap<StringKey, V, BuildHasherDefault<StringKeyHasher>>;
+
+/// Describes where a closure captures an upvalue from when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpvalueRef {
+    /// Whether the captured variable is a local of the immediately enclosing function, rather
+    /// than one of its upvalues.
+    pub is_local: bool,
+    pub index: usize,
+}
+
+/// How many functions deep declarations may nest. Loading bytecode recurses once per level, so
+/// this keeps files from elsewhere from overflowing the stack.
+pub const MAX_NESTING: usize = 256;
+
+#[derive(Debug, Default)]
+pub struct Function {
+    pub arity: usize,
+    pub chunk: Chunk,
+    /// `None` for the implicit function wrapping top-level code.
+    pub name: Option<ObjRef>,
+    pub upvalues: Vec<UpvalueRef>,
+}
+
+impl Function {
+    pub fn new(name: Option<ObjRef>) -> Self {
+        Self {
+            arity: 0,
+            chunk: Chunk::new(),
+            name,
+            upvalues: Vec::new(),
+        }
+    }
+}
+
+/// A variable captured by a closure. It points into the VM stack while the variable is still in
+/// scope and owns the value once the variable's frame has been popped.
+#[derive(Debug, Clone, Copy)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+impl Closure {
+    pub fn new(function: ObjRef, upvalue_count: usize) -> Self {
+        let upvalues = Vec::with_capacity(upvalue_count);
+        Self { function, upvalues }
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: ObjRef,
+    /// Methods keyed by their interned name.
+    pub methods: Table<ObjRef>,
+}
+
+impl Class {
+    pub fn new(name: ObjRef) -> Self {
+        Self {
+            name,
+            methods: Table::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: ObjRef,
+    /// Fields keyed by their interned name.
+    pub fields: Table<Value>,
+}
+
+impl Instance {
+    pub fn new(class: ObjRef) -> Self {
+        Self {
+            class,
+            fields: Table::default(),
+        }
+    }
+}
+
+/// A method that has been accessed on an instance and remembers that instance as `this`.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
@@ -0,0 +1,118 @@
+//! The peephole pass run over each function's code at `-O1`.
+
+use crate::chunk::{Chunk, OpCode};
+
+/// Rewrites pairs of instructions in `chunk` into single instructions, and drops pairs that
+/// push a value only to pop it:
+///
+/// ```text
+/// Equal Not       -> NotEqual
+/// Less Not        -> GreaterEqual
+/// Greater Not     -> LessEqual
+/// Constant k Add  -> AddConstant k
+/// GetLocal s Pop  ->
+/// ```
+///
+/// A pair is left alone when something jumps to its second instruction. Jumps are retargeted
+/// past the removed bytes and each new instruction takes the location of the second one in its
+/// pair, which is where the pair's runtime errors were reported.
+///
+/// A `GetGlobal` followed by `Pop` looks dead but is kept: it fails when the global is
+/// undefined.
+pub fn optimize(chunk: &mut Chunk) {
+    let targets = jump_targets(chunk);
+    let mut out = Chunk::new();
+    // Where each instruction of the old code starts in the new code.
+    let mut moved = vec![0; chunk.code.len() + 1];
+    // Operand offsets of the new jumps, with their old targets.
+    let mut jumps = Vec::new();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        moved[offset] = out.code.len();
+        let (op, operand, next) = decode(chunk, offset);
+        if next < chunk.code.len() && !targets[next] {
+            let (second, _, after) = decode(chunk, next);
+            if is_dead(op, second) {
+                offset = after;
+                continue;
+            }
+            if let Some(op) = combine(op, second) {
+                let location = chunk.location(next);
+                out.write(op as u8, location);
+                out.write_operand(operand, op.operand_width(), location);
+                offset = after;
+                continue;
+            }
+        }
+        match op {
+            OpCode::Jump | OpCode::JumpIfFalse => jumps.push((out.code.len() + 1, next + operand)),
+            OpCode::Loop => jumps.push((out.code.len() + 1, next - operand)),
+            _ => (),
+        }
+        let location = chunk.location(offset);
+        out.write(op as u8, location);
+        out.write_operand(operand, op.operand_width(), location);
+        offset = next;
+    }
+    moved[chunk.code.len()] = out.code.len();
+
+    for (at, target) in jumps {
+        let next = at + 2;
+        let target = moved[target];
+        let jump = next.abs_diff(target);
+        out.patch_jump(at, u16::try_from(jump).expect("Jumps only get shorter."));
+    }
+    chunk.replace_code(out);
+}
+
+/// The instruction doing the work of `first` then `second`, taking `first`'s operand.
+const fn combine(first: OpCode, second: OpCode) -> Option<OpCode> {
+    match (first, second) {
+        (OpCode::Equal, OpCode::Not) => Some(OpCode::NotEqual),
+        (OpCode::Less, OpCode::Not) => Some(OpCode::GreaterEqual),
+        (OpCode::Greater, OpCode::Not) => Some(OpCode::LessEqual),
+        (OpCode::Constant, OpCode::Add) => Some(OpCode::AddConstant),
+        (OpCode::ConstantLong, OpCode::Add) => Some(OpCode::AddConstantLong),
+        _ => None,
+    }
+}
+
+/// Whether `first` then `second` leave the stack as it was and cannot fail.
+const fn is_dead(first: OpCode, second: OpCode) -> bool {
+    matches!(
+        (first, second),
+        (
+            OpCode::GetLocal
+                | OpCode::GetUpvalue
+                | OpCode::Constant
+                | OpCode::ConstantLong
+                | OpCode::Nil
+                | OpCode::True
+                | OpCode::False,
+            OpCode::Pop
+        )
+    )
+}
+
+/// The instruction at `offset`, its operand and the offset of the next instruction.
+fn decode(chunk: &Chunk, offset: usize) -> (OpCode, usize, usize) {
+    let op = OpCode::from_byte(chunk.code[offset]).expect("The compiler emits valid opcodes.");
+    let operand = chunk.read_operand(offset + 1, op.operand_width());
+    (op, operand, offset + 1 + op.operand_width())
+}
+
+/// Which offsets some jump lands on.
+fn jump_targets(chunk: &Chunk) -> Vec<bool> {
+    let mut targets = vec![false; chunk.code.len() + 1];
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (op, operand, next) = decode(chunk, offset);
+        match op {
+            OpCode::Jump | OpCode::JumpIfFalse => targets[next + operand] = true,
+            OpCode::Loop => targets[next - operand] = true,
+            _ => (),
+        }
+        offset = next;
+    }
+    targets
+}
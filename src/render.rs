@@ -0,0 +1,304 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+use crate::codes::ErrorCode;
+use crate::error::{Diagnostic, Error, RuntimeError};
+use crate::scanner::Span;
+use crate::value::{Column, Line};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The value of `--error-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human {
+        color: bool,
+    },
+    /// One JSON object per error and line, for editors and CI. See [`Renderer::json`].
+    Json,
+}
+
+/// The value of `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Never,
+    Always,
+    /// Colour only when stderr is a terminal and `NO_COLOR` is not set.
+    Auto,
+}
+
+impl ColorChoice {
+    pub fn parse(choice: &str) -> Option<Self> {
+        match choice {
+            "never" => Some(Self::Never),
+            "always" => Some(Self::Always),
+            "auto" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn enabled(self) -> bool {
+        match self {
+            Self::Never => false,
+            Self::Always => true,
+            Self::Auto => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+}
+
+/// Renders errors in the style of rustc: a headline, the offending source line with the
+/// token underlined, then any notes and help.
+///
+/// ```text
+/// error[L0007]: Invalid assignment target.
+///  --> script.lox:3:11
+///   |
+/// 3 | if (a + b = c) print a;
+///   |           ^
+///   = help: did you mean `==`?
+/// ```
+#[derive(Debug)]
+pub struct Renderer<'a> {
+    file: &'a str,
+    source: &'a str,
+    format: ErrorFormat,
+    color: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub const fn new(file: &'a str, source: &'a str, format: ErrorFormat) -> Self {
+        let color = matches!(format, ErrorFormat::Human { color: true });
+        Self {
+            file,
+            source,
+            format,
+            color,
+        }
+    }
+
+    /// Prints `error` to stderr.
+    pub fn report(&self, error: &Error) {
+        let rendered = match self.format {
+            ErrorFormat::Human { .. } => self.render(error),
+            ErrorFormat::Json => self.json(error),
+        };
+        eprint!("{rendered}");
+    }
+
+    pub fn render(&self, error: &Error) -> String {
+        match error {
+            Error::Compiler(diagnostics) => diagnostics
+                .iter()
+                .map(|diagnostic| self.diagnostic(diagnostic))
+                .collect(),
+            Error::Runtime(error) => self.runtime(error),
+            Error::InvalidBytecode | Error::Load(_) | Error::Verify(_) | Error::Trace { .. } => {
+                self.headline(None, &error.to_string())
+            }
+            Error::IO(error) => {
+                self.headline(None, &format!("couldn't read {}: {error}", self.file))
+            }
+        }
+    }
+
+    /// Renders `error` as JSON lines, one object per compile error or one for the whole
+    /// runtime error:
+    ///
+    /// ```text
+    /// {"severity":"error","code":"L0104","message":"...","file":"a.lox","line":3,"column":11,
+    ///  "span":{"start":20,"end":21},"backtrace":[{"function":"f","line":3,"column":11}]}
+    /// ```
+    ///
+    /// `code` is `null` for errors that have none, such as failing to read the file. `line`,
+    /// `column` and `span` are `null` for errors with no location, and `backtrace` is
+    /// empty for anything but runtime errors. `function` is `null` for top-level code.
+    pub fn json(&self, error: &Error) -> String {
+        match error {
+            Error::Compiler(diagnostics) => diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    let location = (diagnostic.line, diagnostic.column, diagnostic.span);
+                    let code = Some(diagnostic.code);
+                    self.json_line(code, &diagnostic.message, Some(location), "")
+                })
+                .collect(),
+            Error::Runtime(error) => {
+                let location = error
+                    .backtrace
+                    .first()
+                    .map(|frame| (frame.line, frame.column, frame.span));
+                let backtrace: Vec<String> = error
+                    .backtrace
+                    .iter()
+                    .map(|frame| {
+                        let function = frame
+                            .function
+                            .as_deref()
+                            .map_or_else(|| "null".to_owned(), json_string);
+                        format!(
+                            r#"{{"function":{function},"line":{},"column":{}}}"#,
+                            frame.line, frame.column
+                        )
+                    })
+                    .collect();
+                let backtrace = backtrace.join(",");
+                self.json_line(Some(error.code), &error.message, location, &backtrace)
+            }
+            Error::InvalidBytecode | Error::Load(_) | Error::Verify(_) | Error::Trace { .. } => {
+                self.json_line(None, &error.to_string(), None, "")
+            }
+            Error::IO(error) => {
+                let message = format!("couldn't read {}: {error}", self.file);
+                self.json_line(None, &message, None, "")
+            }
+        }
+    }
+
+    fn json_line(
+        &self,
+        code: Option<ErrorCode>,
+        message: &str,
+        location: Option<(Line, Column, Span)>,
+        backtrace: &str,
+    ) -> String {
+        let location = location.map_or_else(
+            || r#""line":null,"column":null,"span":null"#.to_owned(),
+            |(line, column, span)| {
+                format!(
+                    r#""line":{line},"column":{column},"span":{{"start":{},"end":{}}}"#,
+                    span.start, span.end
+                )
+            },
+        );
+        let code = code.map_or_else(|| "null".to_owned(), |code| json_string(code.code()));
+        format!(
+            r#"{{"severity":"error","code":{code},"message":{},"file":{},{location},"backtrace":[{backtrace}]}}"#,
+            json_string(message),
+            json_string(self.file),
+        ) + "\n"
+    }
+
+    fn diagnostic(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = self.headline(Some(diagnostic.code), &diagnostic.message);
+        let gutter = self.snippet(
+            &mut out,
+            diagnostic.line,
+            diagnostic.column,
+            diagnostic.span,
+        );
+        for note in &diagnostic.notes {
+            self.footer(&mut out, gutter, "note", note);
+        }
+        if let Some(help) = &diagnostic.help {
+            self.footer(&mut out, gutter, "help", help);
+        }
+        out
+    }
+
+    fn runtime(&self, error: &RuntimeError) -> String {
+        let mut out = self.headline(Some(error.code), &error.message);
+        if let Some(frame) = error.backtrace.first() {
+            self.snippet(&mut out, frame.line, frame.column, frame.span);
+        }
+        for frame in &error.backtrace {
+            let _ = writeln!(out, "{frame}");
+        }
+        out
+    }
+
+    fn headline(&self, code: Option<ErrorCode>, message: &str) -> String {
+        let severity = code.map_or_else(|| "error".to_owned(), |code| format!("error[{code}]"));
+        format!(
+            "{}: {}\n",
+            self.paint(RED, &severity),
+            self.paint(BOLD, message)
+        )
+    }
+
+    /// Writes the location and source line of `span` followed by a line of carets under it,
+    /// and returns the gutter width for any footers. Locations outside the source, such as
+    /// those of code the compiler made up, get no snippet.
+    fn snippet(&self, out: &mut String, line: Line, column: Column, span: Span) -> usize {
+        let line_number = line.to_string();
+        let gutter = line_number.len();
+        let blank = " ".repeat(gutter);
+        let _ = writeln!(
+            out,
+            "{blank}{} {}:{line}:{column}",
+            self.paint(BLUE, "-->"),
+            self.file
+        );
+        // Errors from compiled bytecode have locations but no source to show them in, and a
+        // span that does not fit the source cannot be from it.
+        if line == 0
+            || self.source.is_empty()
+            || span.start > span.end
+            || !self.source.is_char_boundary(span.start)
+            || !self.source.is_char_boundary(span.end)
+        {
+            return gutter;
+        }
+        let line_start = self.source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[span.start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| span.start + i);
+        let text = self.source[line_start..line_end].trim_end_matches('\r');
+        // Keep tabs so the carets line up with the text above them.
+        let indent: String = self.source[line_start..span.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.source[span.start..span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let bar = self.paint(BLUE, "|");
+        let _ = writeln!(out, "{blank} {bar}");
+        let _ = writeln!(out, "{} {bar} {text}", self.paint(BLUE, &line_number));
+        let carets = self.paint(RED, &"^".repeat(width));
+        let _ = writeln!(out, "{blank} {bar} {indent}{carets}");
+        gutter
+    }
+
+    fn footer(&self, out: &mut String, gutter: usize, label: &str, message: &str) {
+        let blank = " ".repeat(gutter);
+        let _ = writeln!(
+            out,
+            "{blank} {} {}: {message}",
+            self.paint(BLUE, "="),
+            self.paint(BOLD, label)
+        );
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+/// Quotes and escapes `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,62 @@
+use std::io::{self, Write};
+
+use crate::error::RloxResult;
+use crate::render::{ErrorFormat, Renderer};
+use crate::scanner::{Scanner, TokenKind};
+use crate::value::Line;
+use crate::vm::VM;
+
+/// File name shown in diagnostics for REPL entries.
+const STDIN: &str = "<stdin>";
+
+/// Reads entries from stdin and runs each one on `vm` until end of input. An entry spans as
+/// many lines as it takes to close every `(` and `{` it opens.
+pub fn run(vm: &mut VM, format: ErrorFormat) -> RloxResult {
+    vm.set_repl(true);
+    read_entries(vm, format).inspect_err(|e| Renderer::new(STDIN, "", format).report(e))
+}
+
+fn read_entries(vm: &mut VM, format: ErrorFormat) -> RloxResult {
+    let mut entry = String::new();
+    // Every entry so far. Functions outlive the entry that defined them, so errors are shown
+    // against the whole session, which their spans index into.
+    let mut session = String::new();
+    let mut lines = 0;
+    loop {
+        print!("{}", if entry.is_empty() { "> " } else { "... " });
+        io::stdout().flush()?;
+        if io::stdin().read_line(&mut entry)? == 0 {
+            println!();
+            return Ok(());
+        }
+        if !is_complete(&entry) {
+            continue;
+        }
+        let source = std::mem::take(&mut entry);
+        if source.trim().is_empty() {
+            continue;
+        }
+        vm.set_source_offset(session.len(), lines);
+        session.push_str(&source);
+        lines += Line::try_from(source.matches('\n').count()).unwrap_or(Line::MAX);
+        // The session carries on after an error, with the globals it had.
+        if let Err(e) = vm.interpret(&source) {
+            Renderer::new(STDIN, &session, format).report(&e);
+        }
+    }
+}
+
+/// Whether `source` closes every bracket it opens, so the REPL stops asking for more lines.
+fn is_complete(source: &str) -> bool {
+    let mut scanner = Scanner::new();
+    scanner.update_source(source);
+    let mut depth: usize = 0;
+    loop {
+        match scanner.scan_token().kind {
+            TokenKind::LeftParen | TokenKind::LeftBrace => depth += 1,
+            TokenKind::RightParen | TokenKind::RightBrace => depth = depth.saturating_sub(1),
+            TokenKind::Eof => return depth == 0,
+            _ => (),
+        }
+    }
+}
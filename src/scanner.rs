@@ -1,4 +1,6 @@
-use crate::value::Line;
+use std::fmt::Display;
+
+use crate::value::{Column, Line};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenKind {
@@ -49,29 +51,82 @@ pub enum TokenKind {
     Eof,
 }
 
-#[derive(Debug, Clone)]
+/// Message of the error token for a string missing its closing quote.
+pub const UNTERMINATED_STRING: &str = "Unterminated string.";
+
+/// Byte range of a token in the source it was scanned from. Columns elsewhere count characters,
+/// but spans always count bytes so they can slice the source directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Token<'src> {
     pub kind: TokenKind,
+    /// The token's text, or the error message for [`TokenKind::Error`] tokens.
     pub lexeme: &'src str,
     pub line: Line,
+    /// One-based column of the token's first character.
+    pub column: Column,
+    pub span: Span,
 }
 
 impl<'src> Token<'src> {
-    const fn new(token_type: TokenKind, lexeme: &'src str, line: Line) -> Self {
+    const fn new(
+        token_type: TokenKind,
+        lexeme: &'src str,
+        line: Line,
+        column: Column,
+        span: Span,
+    ) -> Self {
         Self {
             kind: token_type,
             lexeme,
             line,
+            column,
+            span,
         }
     }
 }
 
-impl<'src> Default for Token<'src> {
+/// Formats the token as `line:column Kind 'lexeme' start..end`, one per line in `--tokens`.
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let position = format!("{}:{}", self.line, self.column);
+        let kind = format!("{:?}", self.kind);
+        write!(f, "{position:<8} {kind:<13}")?;
+        match self.kind {
+            TokenKind::Error => write!(f, "{}", self.lexeme)?,
+            _ => write!(f, "'{}'", self.lexeme)?,
+        }
+        write!(f, " {}..{}", self.span.start, self.span.end)
+    }
+}
+
+impl<'src> Token<'src> {
+    /// Creates an identifier token that does not appear in the source, such as the implicit
+    /// `this` of a method.
+    pub const fn synthetic(lexeme: &'src str) -> Self {
+        Self::new(TokenKind::Identifier, lexeme, 0, 0, Span::new(0, 0))
+    }
+}
+
+impl Default for Token<'_> {
     fn default() -> Self {
         Self {
             kind: TokenKind::Eof,
             lexeme: "",
             line: 0,
+            column: 0,
+            span: Span::default(),
         }
     }
 }
@@ -82,6 +137,10 @@ pub struct Scanner<'src> {
     start: usize,
     current: usize,
     line: Line,
+    /// Offset up to which columns have been counted, and the zero-based column there. Token
+    /// starts only move forward, so each character is counted once.
+    counted: usize,
+    column: usize,
 }
 
 impl<'src> Scanner<'src> {
@@ -91,10 +150,12 @@ impl<'src> Scanner<'src> {
             start: 0,
             current: 0,
             line: 1,
+            counted: 0,
+            column: 0,
         }
     }
 
-    pub fn update_source(&mut self, source: &'src str) {
+    pub const fn update_source(&mut self, source: &'src str) {
         self.source = source;
     }
 
@@ -103,22 +164,21 @@ impl<'src> Scanner<'src> {
         let source = &self.source[self.start..];
         let mut iter = source.chars().peekable();
         while let Some(c) = iter.next() {
-            self.current += 1;
+            self.current += c.len_utf8();
             match c {
                 '\t' | ' ' | '\r' => {
                     self.start += 1;
-                    continue;
                 }
                 '\n' => {
                     self.start += 1;
                     self.line += 1;
-                    continue;
                 }
                 '/' if iter.peek().is_some_and(|c| *c == '/') => {
-                    while iter.next().is_some_and(|c| c != '\n') {
-                        self.current += 1;
+                    // The newline ending the comment is left for the whitespace arm above.
+                    while let Some(c) = iter.next_if(|c| *c != '\n') {
+                        self.current += c.len_utf8();
                     }
-                    self.line += 1;
+                    self.start = self.current;
                 }
                 '(' => return self.create_token(TokenKind::LeftParen),
                 ')' => return self.create_token(TokenKind::RightParen),
@@ -144,18 +204,20 @@ impl<'src> Scanner<'src> {
                 }
                 '=' => return self.create_token(TokenKind::Equal),
                 '<' if iter.peek().is_some_and(|c| *c == '=') => {
+                    iter.next();
                     self.current += 1;
                     return self.create_token(TokenKind::LessEqual);
                 }
                 '<' => return self.create_token(TokenKind::Less),
                 '>' if iter.peek().is_some_and(|c| *c == '=') => {
+                    iter.next();
                     self.current += 1;
                     return self.create_token(TokenKind::GreaterEqual);
                 }
                 '>' => return self.create_token(TokenKind::Greater),
                 '"' => {
                     for c in iter.by_ref() {
-                        self.current += 1;
+                        self.current += c.len_utf8();
                         if c == '\n' {
                             self.line += 1;
                         }
@@ -163,36 +225,67 @@ impl<'src> Scanner<'src> {
                             return self.create_token(TokenKind::String);
                         }
                     }
-                    return self.error_token("Unterminated string");
+                    return self.error_token(UNTERMINATED_STRING);
                 }
                 '0'..='9' => {
-                    while iter.peek().is_some_and(|c| c.is_ascii_digit() || *c == '.') {
-                        let _ = iter.next();
-                        self.current += 1;
+                    // Digits, then a fractional part only if a digit follows the dot, so
+                    // `1.2.3` and `1..2` scan as several tokens like they do in clox.
+                    let bytes = self.source.as_bytes();
+                    let digits = |at: usize| {
+                        bytes[at..]
+                            .iter()
+                            .take_while(|b| b.is_ascii_digit())
+                            .count()
+                    };
+                    self.current += digits(self.current);
+                    if bytes.get(self.current) == Some(&b'.')
+                        && bytes.get(self.current + 1).is_some_and(u8::is_ascii_digit)
+                    {
+                        self.current += 1 + digits(self.current + 1);
                     }
                     return self.create_token(TokenKind::Number);
                 }
                 'a'..='z' | 'A'..='Z' | '_' => {
-                    while iter
-                        .peek()
-                        .is_some_and(|c| c.is_alphanumeric() || *c == '_')
-                    {
-                        let _ = iter.next();
-                        self.current += 1;
+                    while let Some(c) = iter.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                        self.current += c.len_utf8();
                     }
                     return self.create_token(self.identifier_type());
                 }
-                _ => return self.error_token("Invalid syntax"),
+                _ => return self.error_token("Unexpected character."),
             }
         }
-        Token::new(TokenKind::Eof, "", self.line)
+        self.start = self.current;
+        let (line, column) = self.position();
+        let end = Span::new(self.current, self.current);
+        Token::new(TokenKind::Eof, "", line, column, end)
     }
 
-    fn create_token(&self, token_type: TokenKind) -> Token<'src> {
+    fn create_token(&mut self, token_type: TokenKind) -> Token<'src> {
+        let (line, column) = self.position();
         Token::new(
             token_type,
             &self.source[self.start..self.current],
-            self.line,
+            line,
+            column,
+            Span::new(self.start, self.current),
+        )
+    }
+
+    /// Line and column of the token being scanned. Tokens such as strings may span several
+    /// lines, and are placed where they start.
+    fn position(&mut self) -> (Line, Column) {
+        let lexeme = &self.source[self.start..self.current];
+        let newlines = lexeme.bytes().filter(|&b| b == b'\n').count();
+        let line = self.line - Line::try_from(newlines).unwrap_or(Line::MAX);
+        let skipped = &self.source[self.counted..self.start];
+        self.column = match skipped.rfind('\n') {
+            Some(newline) => skipped[newline + 1..].chars().count(),
+            None => self.column + skipped.chars().count(),
+        };
+        self.counted = self.start;
+        (
+            line,
+            Column::try_from(self.column + 1).unwrap_or(Column::MAX),
         )
     }
 
@@ -211,7 +304,7 @@ impl<'src> Scanner<'src> {
             's' => self.check_keyword("uper", TokenKind::Super),
             'v' => self.check_keyword("ar", TokenKind::Var),
             'w' => self.check_keyword("hile", TokenKind::While),
-            'f' => match dbg!(chars.peek()) {
+            'f' => match chars.peek() {
                 Some('a') => self.check_keyword("alse", TokenKind::False),
                 Some('o') => self.check_keyword("or", TokenKind::For),
                 Some('u') => self.check_keyword("un", TokenKind::Fun),
@@ -226,17 +319,18 @@ impl<'src> Scanner<'src> {
         }
     }
 
-    fn check_keyword(&self, rest: &'src str, token_type: TokenKind) -> TokenKind {
-        let source = &mut self.source[self.start+1..].chars();
-        for a in rest.chars() {
-            if !source.next().is_some_and(|c| c == a) {
-                return TokenKind::Identifier;
-            }
+    /// Returns `token_type` if the identifier is exactly its first character followed by `rest`.
+    fn check_keyword(&self, rest: &str, token_type: TokenKind) -> TokenKind {
+        if &self.source[self.start + 1..self.current] == rest {
+            token_type
+        } else {
+            TokenKind::Identifier
         }
-        token_type
     }
 
-    const fn error_token(&self, message: &'src str) -> Token<'src> {
-        Token::new(TokenKind::Error, message, self.line)
+    fn error_token(&mut self, message: &'src str) -> Token<'src> {
+        let (line, column) = self.position();
+        let span = Span::new(self.start, self.current);
+        Token::new(TokenKind::Error, message, line, column, span)
     }
 }
@@ -1,28 +1,31 @@
+use crate::heap::ObjRef;
+use crate::object::{BoundMethod, Class, Closure, Function, Instance, LoxString, Upvalue};
+
 pub type Double = f64;
-pub type Line = u16;
+pub type Line = u32;
+pub type Column = u32;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+/// Payload of an object allocated on the [`crate::heap::Heap`].
+#[derive(Debug)]
 pub enum ObjectType {
-    String(String),
+    String(LoxString),
+    Function(Function),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     Bool(bool),
     Nil,
     Number(Double),
-    Object(ObjectType),
-}
-
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
-pub enum Constant<'src> {
-    String(&'src str),
-    Number(Double),
+    Object(ObjRef),
 }
 
-// TODO: Implement object type to enable garbage collection
-#[derive(Debug)]
-struct _Object<T> {
-    kind: ObjectType,
-    next: *mut T,
+/// Lox treats `nil` and `false` as false and every other value as true.
+pub const fn is_falsey(value: Value) -> bool {
+    matches!(value, Value::Bool(false) | Value::Nil)
 }
@@ -0,0 +1,376 @@
+use std::fmt::Display;
+
+use crate::chunk::{Chunk, OpCode};
+use crate::heap::{Heap, ObjRef};
+use crate::object::Function;
+use crate::value::{ObjectType, Value};
+
+/// Bytecode that would make the VM misbehave, found before running it.
+#[derive(Debug)]
+pub struct VerifyError {
+    /// Name of the function holding the bad instruction; `None` for top-level code.
+    pub function: Option<String>,
+    /// Offset of the bad instruction in its chunk.
+    pub offset: usize,
+    pub problem: Problem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    UnknownOpcode(u8),
+    /// The instruction's operand runs past the end of the code.
+    TruncatedOperand,
+    ConstantOutOfRange(usize),
+    /// The constant at the index is not of the kind the instruction needs.
+    WrongConstantType {
+        index: usize,
+        expected: &'static str,
+    },
+    JumpOutOfRange,
+    /// The jump lands inside another instruction's operand.
+    JumpIntoOperand(usize),
+    LocalOutOfRange(usize),
+    UpvalueOutOfRange(usize),
+    /// The script declares upvalues, which nothing would fill since no closure creates it.
+    ScriptUpvalues,
+    /// The instruction pops more values than the stack holds.
+    StackUnderflow,
+    /// Two paths reach the instruction with different numbers of values on the stack.
+    InconsistentStack {
+        expected: usize,
+        found: usize,
+    },
+    /// Execution can run past the last instruction.
+    FallsOffEnd,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let function = self
+            .function
+            .as_ref()
+            .map_or_else(|| "script".to_owned(), |name| format!("{name}()"));
+        write!(
+            f,
+            "Invalid bytecode in {function} at offset {}: ",
+            self.offset
+        )?;
+        match self.problem {
+            Problem::UnknownOpcode(byte) => write!(f, "unknown opcode {byte}."),
+            Problem::TruncatedOperand => write!(f, "operand runs past the end of the code."),
+            Problem::ConstantOutOfRange(index) => {
+                write!(f, "constant {index} is out of range.")
+            }
+            Problem::WrongConstantType { index, expected } => {
+                write!(f, "constant {index} is not a {expected}.")
+            }
+            Problem::JumpOutOfRange => write!(f, "jump target is out of range."),
+            Problem::JumpIntoOperand(target) => {
+                write!(
+                    f,
+                    "jump target {target} is not the start of an instruction."
+                )
+            }
+            Problem::LocalOutOfRange(slot) => write!(f, "local slot {slot} is out of range."),
+            Problem::UpvalueOutOfRange(index) => write!(f, "upvalue {index} is out of range."),
+            Problem::ScriptUpvalues => write!(f, "top-level code cannot have upvalues."),
+            Problem::StackUnderflow => write!(f, "stack underflow."),
+            Problem::InconsistentStack { expected, found } => write!(
+                f,
+                "reached with {found} values on the stack but also with {expected}."
+            ),
+            Problem::FallsOffEnd => write!(f, "execution runs past the end of the code."),
+        }
+    }
+}
+
+/// Checks that `function` and every function nested in it can be run without the VM
+/// indexing out of bounds or misreading a constant: opcodes and operands decode, constant
+/// indices are in range and of the right kind, jumps land on instructions, locals and upvalues
+/// exist, and every path through the code keeps the stack balanced and ends in a return. What
+/// kind of value sits where on the stack is not tracked; the VM checks that itself.
+pub fn verify(heap: &Heap, function: ObjRef) -> Result<(), VerifyError> {
+    Verifier::new(heap, heap.function(function), None).verify()
+}
+
+struct Verifier<'heap> {
+    heap: &'heap Heap,
+    function: &'heap Function,
+    chunk: &'heap Chunk,
+    /// Number of upvalues of the enclosing function, which non-local captures index into;
+    /// `None` for the script.
+    enclosing_upvalues: Option<usize>,
+}
+
+impl<'heap> Verifier<'heap> {
+    const fn new(
+        heap: &'heap Heap,
+        function: &'heap Function,
+        enclosing_upvalues: Option<usize>,
+    ) -> Self {
+        Self {
+            heap,
+            function,
+            chunk: &function.chunk,
+            enclosing_upvalues,
+        }
+    }
+
+    fn verify(&self) -> Result<(), VerifyError> {
+        self.check_captures()?;
+        let starts = self.check_instructions()?;
+        self.check_stack(&starts)?;
+        for &constant in self.chunk.constants() {
+            if let Value::Object(object) = constant {
+                if let ObjectType::Function(nested) = self.heap.get(object) {
+                    Verifier::new(self.heap, nested, Some(self.function.upvalues.len()))
+                        .verify()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn error(&self, offset: usize, problem: Problem) -> VerifyError {
+        let function = self
+            .function
+            .name
+            .map(|name| self.heap.string(name).to_owned());
+        VerifyError {
+            function,
+            offset,
+            problem,
+        }
+    }
+
+    /// Non-local captures must name an upvalue of the enclosing function. Local captures are
+    /// checked against the stack where the closure is created, in [`Self::check_stack`].
+    fn check_captures(&self) -> Result<(), VerifyError> {
+        let Some(enclosing) = self.enclosing_upvalues else {
+            if self.function.upvalues.is_empty() {
+                return Ok(());
+            }
+            return Err(self.error(0, Problem::ScriptUpvalues));
+        };
+        for upvalue in &self.function.upvalues {
+            if !upvalue.is_local && upvalue.index >= enclosing {
+                return Err(self.error(0, Problem::UpvalueOutOfRange(upvalue.index)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes every instruction in order and checks the operands that do not depend on the
+    /// stack. Returns which offsets start an instruction.
+    fn check_instructions(&self) -> Result<Vec<bool>, VerifyError> {
+        let code = &self.chunk.code;
+        let mut starts = vec![false; code.len()];
+        let mut offset = 0;
+        while offset < code.len() {
+            starts[offset] = true;
+            let op = OpCode::from_byte(code[offset])
+                .ok_or_else(|| self.error(offset, Problem::UnknownOpcode(code[offset])))?;
+            let next = offset + 1 + op.operand_width();
+            if next > code.len() {
+                return Err(self.error(offset, Problem::TruncatedOperand));
+            }
+            let operand = self.chunk.read_operand(offset + 1, op.operand_width());
+            self.check_operand(offset, op, operand)?;
+            offset = next;
+        }
+        Ok(starts)
+    }
+
+    fn check_operand(&self, offset: usize, op: OpCode, operand: usize) -> Result<(), VerifyError> {
+        let expected = match op {
+            OpCode::Constant
+            | OpCode::ConstantLong
+            | OpCode::AddConstant
+            | OpCode::AddConstantLong => None,
+            OpCode::GetGlobal
+            | OpCode::GetGlobalLong
+            | OpCode::DefineGlobal
+            | OpCode::DefineGlobalLong
+            | OpCode::SetGlobal
+            | OpCode::SetGlobalLong
+            | OpCode::GetProperty
+            | OpCode::GetPropertyLong
+            | OpCode::SetProperty
+            | OpCode::SetPropertyLong
+            | OpCode::GetSuper
+            | OpCode::GetSuperLong
+            | OpCode::Class
+            | OpCode::ClassLong
+            | OpCode::Method
+            | OpCode::MethodLong => Some("string"),
+            OpCode::Closure | OpCode::ClosureLong => Some("function"),
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                if operand >= self.function.upvalues.len() {
+                    return Err(self.error(offset, Problem::UpvalueOutOfRange(operand)));
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        let Some(&constant) = self.chunk.constants().get(operand) else {
+            return Err(self.error(offset, Problem::ConstantOutOfRange(operand)));
+        };
+        let matches = match (expected, constant) {
+            (None, _) => true,
+            (Some(kind), Value::Object(object)) => matches!(
+                (kind, self.heap.get(object)),
+                ("string", ObjectType::String(_)) | ("function", ObjectType::Function(_))
+            ),
+            (Some(_), _) => false,
+        };
+        if !matches {
+            let expected = expected.unwrap_or_default();
+            return Err(self.error(
+                offset,
+                Problem::WrongConstantType {
+                    index: operand,
+                    expected,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Follows every path through the code, tracking how many values are on the stack,
+    /// slot zero and the parameters included.
+    fn check_stack(&self, starts: &[bool]) -> Result<(), VerifyError> {
+        let code = &self.chunk.code;
+        let mut depths: Vec<Option<usize>> = vec![None; code.len()];
+        let mut pending = vec![(0, self.function.arity + 1)];
+        while let Some((offset, depth)) = pending.pop() {
+            if offset >= code.len() {
+                return Err(self.error(offset, Problem::FallsOffEnd));
+            }
+            match depths[offset] {
+                Some(expected) if expected != depth => {
+                    return Err(self.error(
+                        offset,
+                        Problem::InconsistentStack {
+                            expected,
+                            found: depth,
+                        },
+                    ));
+                }
+                Some(_) => continue,
+                None => depths[offset] = Some(depth),
+            }
+            let op = OpCode::from_byte(code[offset]).expect("Opcodes were checked already.");
+            let operand = self.chunk.read_operand(offset + 1, op.operand_width());
+            let next = offset + 1 + op.operand_width();
+            let (pops, pushes) = stack_effect(op, operand);
+            if depth < pops {
+                return Err(self.error(offset, Problem::StackUnderflow));
+            }
+            match op {
+                OpCode::GetLocal | OpCode::SetLocal if operand >= depth => {
+                    return Err(self.error(offset, Problem::LocalOutOfRange(operand)));
+                }
+                OpCode::Closure | OpCode::ClosureLong => {
+                    self.check_local_captures(offset, operand, depth)?;
+                }
+                _ => (),
+            }
+            let after = depth - pops + pushes;
+            let jump = |target: Option<usize>| {
+                let target = target
+                    .filter(|&target| target < code.len())
+                    .ok_or_else(|| self.error(offset, Problem::JumpOutOfRange))?;
+                if starts[target] {
+                    Ok(target)
+                } else {
+                    Err(self.error(offset, Problem::JumpIntoOperand(target)))
+                }
+            };
+            match op {
+                OpCode::Return => (),
+                OpCode::Jump => pending.push((jump(next.checked_add(operand))?, after)),
+                OpCode::Loop => pending.push((jump(next.checked_sub(operand))?, after)),
+                OpCode::JumpIfFalse => {
+                    pending.push((jump(next.checked_add(operand))?, after));
+                    pending.push((next, after));
+                }
+                _ => pending.push((next, after)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Local captures must name a slot that exists where the closure is created.
+    fn check_local_captures(
+        &self,
+        offset: usize,
+        constant: usize,
+        depth: usize,
+    ) -> Result<(), VerifyError> {
+        let Value::Object(function) = self.chunk.constant(constant) else {
+            return Ok(());
+        };
+        for upvalue in &self.heap.function(function).upvalues {
+            if upvalue.is_local && upvalue.index >= depth {
+                return Err(self.error(offset, Problem::LocalOutOfRange(upvalue.index)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many values `op` pops and then pushes.
+const fn stack_effect(op: OpCode, operand: usize) -> (usize, usize) {
+    match op {
+        OpCode::Constant
+        | OpCode::ConstantLong
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetGlobal
+        | OpCode::GetGlobalLong
+        | OpCode::GetLocal
+        | OpCode::GetUpvalue
+        | OpCode::Closure
+        | OpCode::ClosureLong
+        | OpCode::Class
+        | OpCode::ClassLong => (0, 1),
+        OpCode::Pop
+        | OpCode::DefineGlobal
+        | OpCode::DefineGlobalLong
+        | OpCode::Print
+        | OpCode::CloseUpvalue
+        | OpCode::Return => (1, 0),
+        OpCode::SetGlobal
+        | OpCode::SetGlobalLong
+        | OpCode::SetLocal
+        | OpCode::SetUpvalue
+        | OpCode::GetProperty
+        | OpCode::GetPropertyLong
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::JumpIfFalse
+        | OpCode::AddConstant
+        | OpCode::AddConstantLong => (1, 1),
+        OpCode::SetProperty
+        | OpCode::SetPropertyLong
+        | OpCode::GetSuper
+        | OpCode::GetSuperLong
+        | OpCode::Equal
+        | OpCode::Greater
+        | OpCode::Less
+        | OpCode::NotEqual
+        | OpCode::GreaterEqual
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Inherit
+        | OpCode::Method
+        | OpCode::MethodLong => (2, 1),
+        OpCode::Jump | OpCode::Loop => (0, 0),
+        // The callee and its arguments are replaced by the result.
+        OpCode::Call => (operand + 1, 1),
+    }
+}
@@ -1,176 +1,725 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+use std::io::Write;
 
+use crate::bytecode;
 use crate::chunk::{Chunk, OpCode};
+use crate::codes::ErrorCode;
 use crate::compiler::Compiler;
-use crate::error::{Error, RloxResult};
-use crate::value::{Constant, ObjectType, Value};
+use crate::disassembler;
+use crate::error::{Error, RloxResult, RuntimeError, TraceFrame};
+use crate::heap::{Heap, ObjRef};
+use crate::object::{BoundMethod, Class, Closure, Instance, Table, Upvalue};
+use crate::value::{is_falsey, Double, Line, ObjectType, Value};
+use crate::verifier;
+
+const FRAMES_MAX: usize = 64;
 
 #[derive(Debug)]
-pub struct VM<'src> {
-    chunk: Chunk<'src>,
+struct CallFrame {
+    closure: ObjRef,
+    /// The closure's function, cached to save a heap lookup on every instruction.
+    function: ObjRef,
     ip: usize,
+    /// Index of the frame's first stack slot, which holds the called function.
+    slots: usize,
+}
+
+/// Where `--trace` output goes, and what to call it if writing fails.
+struct Trace {
+    out: Box<dyn Write>,
+    name: String,
+}
+
+impl Trace {
+    fn error(&self, error: std::io::Error) -> Error {
+        Error::Trace {
+            name: self.name.clone(),
+            error,
+        }
+    }
+}
+
+impl std::fmt::Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Trace({})", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct VM {
+    frames: Vec<CallFrame>,
     stack: Vec<Value>,
-    globals: HashMap<String, Value>,
-    compiler: Compiler<'src>,
+    globals: Table<Value>,
+    /// Upvalues still pointing into the stack, ordered by the stack slot they capture.
+    open_upvalues: Vec<ObjRef>,
+    heap: Heap,
+    /// The interned `"init"`, kept around to look up initializers without hashing.
+    init_string: ObjRef,
+    repl: bool,
+    /// Where the next source passed to [`Self::interpret`] starts in the REPL session, in bytes
+    /// and in lines.
+    source_offset: (usize, Line),
+    optimize: bool,
+    trace: Option<Trace>,
 }
 
-impl<'src> VM<'src> {
+impl VM {
     pub fn new() -> Self {
-        let chunk = Chunk::new();
-        let ip = 0;
+        let frames = Vec::with_capacity(FRAMES_MAX);
         let stack = Vec::with_capacity(256);
-        let globals = HashMap::new();
-        let compiler = Compiler::new();
+        let globals = Table::default();
+        let open_upvalues = Vec::new();
+        let mut heap = Heap::new();
+        let init_string = heap.intern("init");
         Self {
-            chunk,
-            ip,
+            frames,
             stack,
             globals,
-            compiler,
+            open_upvalues,
+            heap,
+            init_string,
+            repl: false,
+            source_offset: (0, 0),
+            optimize: true,
+            trace: None,
         }
     }
 
+    /// Collects garbage before every allocation instead of only when the heap has grown.
+    pub const fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    /// Compiles the source the way the REPL wants it; see [`Compiler::set_repl`].
+    pub const fn set_repl(&mut self, repl: bool) {
+        self.repl = repl;
+    }
+
+    /// Makes spans and lines in errors and locations refer to a text in which the source starts
+    /// `offset` bytes and `lines` lines in; see [`Compiler::set_offset`].
+    pub const fn set_source_offset(&mut self, offset: usize, lines: Line) {
+        self.source_offset = (offset, lines);
+    }
+
+    /// Runs the peephole optimizer over compiled code, as `-O1` asks; on by default.
+    pub const fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Writes the stack and the disassembled instruction to `out` before every instruction is
+    /// executed, like clox's `DEBUG_TRACE_EXECUTION`. `name` identifies `out` in errors.
+    pub fn set_trace(&mut self, name: &str, out: Box<dyn Write>) {
+        self.trace = Some(Trace {
+            out,
+            name: name.to_owned(),
+        });
+    }
+
+    /// Compiles and runs `source`. Globals survive the call, so the VM can be fed one REPL entry
+    /// after another; after an error the stack is reset but the globals are left as they were.
+    pub fn interpret(&mut self, source: &str) -> RloxResult {
+        let function = self.compile(source)?;
+        self.execute(function)
+    }
+
+    /// Runs a script previously compiled with [`Self::compile_to_bytecode`]. The file may come
+    /// from anywhere, so it is verified first.
+    pub fn run_bytecode(&mut self, bytes: &[u8]) -> RloxResult {
+        let function = bytecode::read(&mut self.heap, bytes).map_err(Error::Load)?;
+        verifier::verify(&self.heap, function).map_err(Error::Verify)?;
+        self.execute(function)
+    }
+
+    /// Compiles `source` into the `.loxc` format without running it.
+    pub fn compile_to_bytecode(&mut self, source: &str) -> Result<Vec<u8>, Error> {
+        let function = self.compile(source)?;
+        Ok(bytecode::write(&self.heap, function, source))
+    }
+
+    fn execute(&mut self, function: ObjRef) -> RloxResult {
+        self.stack.push(Value::Object(function));
+        let closure = self.alloc(ObjectType::Closure(Closure::new(function, 0)));
+        self.stack.pop();
+        self.stack.push(Value::Object(closure));
+        let result = self.call(closure, 0).and_then(|()| self.run());
+        // Flushed even when the script failed, since that is when the trace is most wanted.
+        let flushed = self.flush_trace();
+        let result = result.and(flushed);
+        if result.is_err() {
+            self.reset_stack();
+        }
+        result
+    }
+
+    /// Compiles `source` without running it and returns the disassembly of its bytecode.
+    pub fn disassemble(&mut self, source: &str) -> Result<String, Error> {
+        let function = self.compile(source)?;
+        Ok(disassembler::disassemble(&self.heap, function))
+    }
+
+    /// Compiles `source` without running it and returns the parse tree the compiler recognised.
+    pub fn ast(&mut self, source: &str) -> Result<String, Error> {
+        let mut compiler = Compiler::new(&mut self.heap);
+        compiler.set_ast(true);
+        compiler.compile(source).map_err(Error::Compiler)?;
+        Ok(compiler
+            .take_ast()
+            .map(|tree| tree.to_string())
+            .unwrap_or_default())
+    }
+
+    fn compile(&mut self, source: &str) -> Result<ObjRef, Error> {
+        let mut compiler = Compiler::new(&mut self.heap);
+        compiler.set_repl(self.repl);
+        let (offset, lines) = self.source_offset;
+        compiler.set_offset(offset, lines);
+        compiler.set_optimize(self.optimize);
+        compiler.compile(source).map_err(Error::Compiler)
+    }
+
+    fn reset_stack(&mut self) {
+        // Closures that escaped into globals outlive the stack, so they keep the values.
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.frames.clear();
+    }
+
     #[allow(clippy::too_many_lines)]
-    pub fn interpret(&'src mut self, source: &'src str) -> RloxResult {
-        self.chunk = self.compiler.compile(source);
-        self.ip = 0;
-        for instruction in &self.chunk.code {
-            self.ip += 1;
-            match instruction {
-                OpCode::Constant(index) => {
-                    let constant = self.chunk.constant(*index);
-                    match constant {
-                        Constant::String(str) => self
-                            .stack
-                            .push(Value::Object(ObjectType::String(str.to_owned()))),
-                        Constant::Number(num) => self.stack.push(Value::Number(num)),
-                    }
+    fn run(&mut self) -> RloxResult {
+        loop {
+            if self.trace.is_some() {
+                self.trace_instruction()?;
+            }
+            let frame = self
+                .frames
+                .last_mut()
+                .expect("VM should always have a call frame.");
+            let chunk = &self.heap.function(frame.function).chunk;
+            let op = OpCode::from_byte(chunk.code[frame.ip]).ok_or(Error::InvalidBytecode)?;
+            let operand = chunk.read_operand(frame.ip + 1, op.operand_width());
+            frame.ip += 1 + op.operand_width();
+            match op {
+                OpCode::Constant | OpCode::ConstantLong => {
+                    let value = self.chunk().constant(operand);
+                    self.stack.push(value);
                 }
                 OpCode::Greater => {
-                    let b = self.stack.pop().ok_or(Error::Compiler)?;
-                    let a = self.stack.pop().ok_or(Error::Compiler)?;
+                    let (a, b) = self.pop_numbers()?;
                     self.stack.push(Value::Bool(a > b));
                 }
                 OpCode::Less => {
-                    let b = self.stack.pop().ok_or(Error::Compiler)?;
-                    let a = self.stack.pop().ok_or(Error::Compiler)?;
+                    let (a, b) = self.pop_numbers()?;
                     self.stack.push(Value::Bool(a < b));
                 }
+                // True for NaN, like the `Less`/`Greater` and `Not` pairs they replace.
+                OpCode::GreaterEqual => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.stack
+                        .push(Value::Bool(a.partial_cmp(&b) != Some(Ordering::Less)));
+                }
+                OpCode::LessEqual => {
+                    let (a, b) = self.pop_numbers()?;
+                    self.stack
+                        .push(Value::Bool(a.partial_cmp(&b) != Some(Ordering::Greater)));
+                }
                 OpCode::Nil => self.stack.push(Value::Nil),
                 OpCode::True => self.stack.push(Value::Bool(true)),
                 OpCode::False => self.stack.push(Value::Bool(false)),
                 OpCode::Pop => {
-                    let _ = self.stack.pop().ok_or(Error::Compiler)?;
-                }
-                OpCode::GetGlobal(n) => {
-                    if let Constant::String(name) = self.chunk.constant(*n) {
-                        let value = self
-                            .globals
-                            .get(name)
-                            .expect("Could not find name in globals.");
-                        // FIXME: It is finally time to deal with pointers...
-                        self.stack.push(value.to_owned());
-                    }
+                    let _ = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                }
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let name = self.read_string(operand);
+                    let Some(&value) = self.globals.get(&self.heap.key(name)) else {
+                        let name = self.heap.string(name);
+                        return Err(self.runtime_error(
+                            ErrorCode::UndefinedVariable,
+                            format!("Undefined variable '{name}'."),
+                        ));
+                    };
+                    self.stack.push(value);
                 }
-                OpCode::DefineGlobal(n) => {
-                    if let Constant::String(name) = self.chunk.constant(*n) {
-                        let value = self.stack.last().ok_or(Error::Compiler)?;
-                        self.globals.insert(name.to_string(), value.to_owned());
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let name = self.read_string(operand);
+                    let value = self.stack.last().ok_or(Error::InvalidBytecode)?;
+                    self.globals.insert(self.heap.key(name), *value);
+                    let _ = self.stack.pop();
+                }
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let name = self.read_string(operand);
+                    let value = self.stack.last().ok_or(Error::InvalidBytecode)?;
+                    if let Some(global) = self.globals.get_mut(&self.heap.key(name)) {
+                        *global = *value;
                     } else {
-                        return Err(Error::Compiler);
+                        let name = self.heap.string(name);
+                        return Err(self.runtime_error(
+                            ErrorCode::UndefinedVariable,
+                            format!("Undefined variable '{name}'."),
+                        ));
                     }
-                    let _ = self.stack.pop();
                 }
-                OpCode::SetGlobal(n) => {
-                    if let Constant::String(name) = self.chunk.constant(*n) {
-                        let value = self.stack.last().ok_or(Error::Compiler)?;
-                        if self.globals.contains_key(name) {
-                            self.globals.insert(name.to_string(), value.to_owned());
-                        } else {
-                            println!("Undefined variable {name}");
-                            return Err(Error::Runtime);
-                        }
+                OpCode::GetLocal => {
+                    let value = self.stack[self.frame().slots + operand];
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal => {
+                    let value = *self.stack.last().ok_or(Error::InvalidBytecode)?;
+                    let slots = self.frame().slots;
+                    self.stack[slots + operand] = value;
+                }
+                OpCode::GetUpvalue => {
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[operand];
+                    let value = match *self.heap.upvalue(upvalue) {
+                        Upvalue::Open(slot) => self.stack[slot],
+                        Upvalue::Closed(value) => value,
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let value = *self.stack.last().ok_or(Error::InvalidBytecode)?;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[operand];
+                    match self.heap.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty | OpCode::GetPropertyLong => {
+                    let name = self.read_string(operand);
+                    let receiver = self.stack.last().ok_or(Error::InvalidBytecode)?;
+                    let Some(ObjectType::Instance(instance)) = self.object(*receiver) else {
+                        return Err(self.runtime_error(
+                            ErrorCode::NotAnInstance,
+                            "Only instances have properties.",
+                        ));
+                    };
+                    let class = instance.class;
+                    if let Some(&value) = instance.fields.get(&self.heap.key(name)) {
+                        self.stack.pop();
+                        self.stack.push(value);
                     } else {
-                        return Err(Error::Compiler);
+                        self.bind_method(class, name)?;
                     }
                 }
+                OpCode::SetProperty | OpCode::SetPropertyLong => {
+                    let name = self.heap.key(self.read_string(operand));
+                    let value = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    let receiver = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    let Some(ObjectType::Instance(instance)) = self.object_mut(receiver) else {
+                        return Err(self.runtime_error(
+                            ErrorCode::NotAnInstance,
+                            "Only instances have fields.",
+                        ));
+                    };
+                    instance.fields.insert(name, value);
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper | OpCode::GetSuperLong => {
+                    let name = self.read_string(operand);
+                    let superclass = self.pop_class()?;
+                    self.bind_method(superclass, name)?;
+                }
                 OpCode::Equal => {
-                    let b = self.stack.pop().ok_or(Error::Compiler)?;
-                    let a = self.stack.pop().ok_or(Error::Compiler)?;
+                    let b = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    let a = self.stack.pop().ok_or(Error::InvalidBytecode)?;
                     self.stack.push(Value::Bool(a == b));
                 }
+                OpCode::NotEqual => {
+                    let b = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    let a = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    self.stack.push(Value::Bool(a != b));
+                }
                 OpCode::Add => {
-                    let b = self.stack.pop().ok_or(Error::Compiler)?;
-                    let a = self.stack.pop().ok_or(Error::Compiler)?;
-                    if let Value::Number(b) = b {
-                        if let Value::Number(a) = a {
-                            self.stack.push(Value::Number(a + b));
-                        } else {
-                            println!("Mismatched types to add.");
-                            return Err(Error::Runtime);
-                        }
-                    }
-                    if let Value::Object(ObjectType::String(b)) = b {
-                        if let Value::Object(ObjectType::String(a)) = a {
-                            self.stack.push(Value::Object(ObjectType::String(a + &b)));
-                        } else {
-                            println!("Mismatched types to add.");
-                            return Err(Error::Runtime);
-                        }
-                    }
+                    let b = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    let a = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    self.add(a, b)?;
+                }
+                OpCode::AddConstant | OpCode::AddConstantLong => {
+                    let b = self.chunk().constant(operand);
+                    let a = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    self.add(a, b)?;
                 }
                 OpCode::Subtract => {
-                    let Value::Number(b) = self.stack.pop().ok_or(Error::Compiler)? else {
-                        return Err(Error::Runtime);
-                    };
-                    let Value::Number(a) = self.stack.pop().ok_or(Error::Compiler)? else {
-                        return Err(Error::Runtime);
-                    };
+                    let (a, b) = self.pop_numbers()?;
                     self.stack.push(Value::Number(a - b));
                 }
                 OpCode::Multiply => {
-                    let Value::Number(b) = self.stack.pop().ok_or(Error::Compiler)? else {
-                        return Err(Error::Runtime);
-                    };
-                    let Value::Number(a) = self.stack.pop().ok_or(Error::Compiler)? else {
-                        return Err(Error::Runtime);
-                    };
+                    let (a, b) = self.pop_numbers()?;
                     self.stack.push(Value::Number(a * b));
                 }
                 OpCode::Divide => {
-                    let Value::Number(b) = self.stack.pop().ok_or(Error::Compiler)? else {
-                        return Err(Error::Runtime);
-                    };
-                    let Value::Number(a) = self.stack.pop().ok_or(Error::Compiler)? else {
-                        return Err(Error::Runtime);
-                    };
+                    let (a, b) = self.pop_numbers()?;
                     self.stack.push(Value::Number(a / b));
                 }
-                OpCode::Not => match self.stack.pop().ok_or(Error::Compiler)? {
-                    Value::Bool(false) | Value::Nil => self.stack.push(Value::Bool(true)),
-                    _ => self.stack.push(Value::Bool(false)),
-                },
+                OpCode::Not => {
+                    let value = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    self.stack.push(Value::Bool(is_falsey(value)));
+                }
                 OpCode::Negate => {
-                    let Value::Number(a) = self.stack.pop().ok_or(Error::Compiler)? else {
-                        return Err(Error::Runtime);
+                    let Value::Number(a) = self.stack.pop().ok_or(Error::InvalidBytecode)? else {
+                        return Err(self
+                            .runtime_error(ErrorCode::TypeMismatch, "Operand must be a number."));
                     };
                     self.stack.push(Value::Number(-a));
                 }
-                OpCode::Print => print_value(self.stack.pop().ok_or(Error::Compiler)?),
+                OpCode::Print => {
+                    let value = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    println!("{}", self.heap.display(value));
+                }
+                OpCode::Jump => self.frame_mut().ip += operand,
+                OpCode::JumpIfFalse => {
+                    if is_falsey(*self.stack.last().ok_or(Error::InvalidBytecode)?) {
+                        self.frame_mut().ip += operand;
+                    }
+                }
+                OpCode::Loop => self.frame_mut().ip -= operand,
+                OpCode::Call => {
+                    let callee = self.stack[self.stack.len() - operand - 1];
+                    self.call_value(callee, operand)?;
+                }
+                OpCode::Closure | OpCode::ClosureLong => {
+                    let Value::Object(function) = self.chunk().constant(operand) else {
+                        return Err(Error::InvalidBytecode);
+                    };
+                    let upvalue_count = self.heap.function(function).upvalues.len();
+                    let closure =
+                        self.alloc(ObjectType::Closure(Closure::new(function, upvalue_count)));
+                    self.stack.push(Value::Object(closure));
+                    let CallFrame {
+                        closure: enclosing,
+                        slots,
+                        ..
+                    } = *self.frame();
+                    for i in 0..upvalue_count {
+                        let upvalue = self.heap.function(function).upvalues[i];
+                        let captured = if upvalue.is_local {
+                            self.capture_upvalue(slots + upvalue.index)
+                        } else {
+                            self.heap.closure(enclosing).upvalues[upvalue.index]
+                        };
+                        self.heap.closure_mut(closure).upvalues.push(captured);
+                    }
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    let _ = self.stack.pop();
+                }
                 OpCode::Return => {
-                    // Exit interpreter
+                    let result = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+                    let frame = self.frames.pop().ok_or(Error::InvalidBytecode)?;
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class | OpCode::ClassLong => {
+                    let name = self.read_string(operand);
+                    let class = self.alloc(ObjectType::Class(Class::new(name)));
+                    self.stack.push(Value::Object(class));
+                }
+                OpCode::Inherit => {
+                    let subclass = self.pop_class()?;
+                    let superclass = self.stack.last().ok_or(Error::InvalidBytecode)?;
+                    let Some(ObjectType::Class(superclass)) = self.object(*superclass) else {
+                        return Err(self.runtime_error(
+                            ErrorCode::SuperclassNotClass,
+                            "Superclass must be a class.",
+                        ));
+                    };
+                    let methods = superclass.methods.clone();
+                    self.heap.class_mut(subclass).methods.extend(methods);
+                }
+                OpCode::Method | OpCode::MethodLong => {
+                    let name = self.read_string(operand);
+                    let method = match self.stack.pop() {
+                        Some(Value::Object(method))
+                            if matches!(self.heap.get(method), ObjectType::Closure(_)) =>
+                        {
+                            method
+                        }
+                        _ => return Err(Error::InvalidBytecode),
+                    };
+                    let class = self.pop_class()?;
+                    self.stack.push(Value::Object(class));
+                    let name = self.heap.key(name);
+                    self.heap.class_mut(class).methods.insert(name, method);
                 }
             }
         }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames
+            .last()
+            .expect("VM should always have a call frame.")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames
+            .last_mut()
+            .expect("VM should always have a call frame.")
+    }
+
+    fn trace_instruction(&mut self) -> RloxResult {
+        let mut line = String::from("          ");
+        for &value in &self.stack {
+            let _ = write!(line, "[ {} ]", self.heap.display(value));
+        }
+        let ip = self.frame().ip;
+        let instruction = disassembler::disassemble_instruction(&self.heap, self.chunk(), ip);
+        if let Some(trace) = &mut self.trace {
+            writeln!(trace.out, "{line}\n{instruction}").map_err(|e| trace.error(e))?;
+        }
         Ok(())
     }
-}
 
-fn print_value(value: Value) {
-    match value {
-        Value::Bool(bool) => println!("{bool}"),
-        Value::Nil => (),
-        Value::Number(num) => println!("{num}"),
-        Value::Object(ObjectType::String(str)) => println!("{str}"),
+    fn flush_trace(&mut self) -> RloxResult {
+        if let Some(trace) = &mut self.trace {
+            trace.out.flush().map_err(|e| trace.error(e))?;
+        }
+        Ok(())
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.heap.function(self.frame().function).chunk
+    }
+
+    /// Returns the string constant at `index` in the current chunk.
+    fn read_string(&self, index: usize) -> ObjRef {
+        match self.chunk().constant(index) {
+            Value::Object(string) => string,
+            constant => panic!("Expected string constant, found {constant:?}."),
+        }
+    }
+
+    /// Pushes the sum of two numbers or the concatenation of two strings.
+    fn add(&mut self, a: Value, b: Value) -> RloxResult {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => {
+                self.stack.push(Value::Number(a + b));
+                return Ok(());
+            }
+            (Value::Object(a), Value::Object(b)) => {
+                if let (ObjectType::String(a), ObjectType::String(b)) =
+                    (self.heap.get(a), self.heap.get(b))
+                {
+                    let result = a.chars.clone() + &b.chars;
+                    let result = self.intern(&result);
+                    self.stack.push(Value::Object(result));
+                    return Ok(());
+                }
+            }
+            _ => (),
+        }
+        Err(self.runtime_error(
+            ErrorCode::TypeMismatch,
+            "Operands must be two numbers or two strings.",
+        ))
+    }
+
+    /// Pops the class that the compiler leaves on the stack for the class instructions. The
+    /// verifier does not track what kind of value is where, so bytecode from a file may have
+    /// put something else there.
+    fn pop_class(&mut self) -> Result<ObjRef, Error> {
+        match self.stack.pop() {
+            Some(Value::Object(class)) if matches!(self.heap.get(class), ObjectType::Class(_)) => {
+                Ok(class)
+            }
+            _ => Err(Error::InvalidBytecode),
+        }
+    }
+
+    /// Pops the two operands of a numeric binary operator, left operand first.
+    fn pop_numbers(&mut self) -> Result<(Double, Double), Error> {
+        let b = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+        let a = self.stack.pop().ok_or(Error::InvalidBytecode)?;
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok((a, b)),
+            _ => Err(self.runtime_error(ErrorCode::TypeMismatch, "Operands must be numbers.")),
+        }
+    }
+
+    /// Builds a runtime error for the instruction being executed, with a backtrace of every
+    /// active call.
+    fn runtime_error(&self, code: ErrorCode, message: impl Into<String>) -> Error {
+        let backtrace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let function = self.heap.function(frame.function);
+                let location = function.chunk.location(frame.ip.saturating_sub(1));
+                TraceFrame {
+                    line: location.line,
+                    column: location.column,
+                    span: location.span,
+                    function: function.name.map(|name| self.heap.string(name).to_owned()),
+                }
+            })
+            .collect();
+        Error::Runtime(RuntimeError {
+            code,
+            message: message.into(),
+            backtrace,
+        })
+    }
+
+    /// Returns the heap object `value` refers to, if it is an object.
+    fn object(&self, value: Value) -> Option<&ObjectType> {
+        match value {
+            Value::Object(object) => Some(self.heap.get(object)),
+            _ => None,
+        }
+    }
+
+    fn object_mut(&mut self, value: Value) -> Option<&mut ObjectType> {
+        match value {
+            Value::Object(object) => Some(self.heap.get_mut(object)),
+            _ => None,
+        }
+    }
+
+    /// Allocates `object`, first collecting garbage if the heap asks for it. Anything `object`
+    /// refers to must already be reachable from a root.
+    fn alloc(&mut self, object: ObjectType) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(object)
+    }
+
+    /// Interns `chars`, first collecting garbage if the heap asks for it.
+    fn intern(&mut self, chars: &str) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(chars)
+    }
+
+    fn collect_garbage(&mut self) {
+        let roots = self
+            .stack
+            .iter()
+            .copied()
+            .chain(
+                self.globals
+                    .iter()
+                    .flat_map(|(name, &value)| [Value::Object(name.string), value]),
+            )
+            .chain(std::iter::once(Value::Object(self.init_string)))
+            .chain(self.frames.iter().map(|frame| Value::Object(frame.closure)))
+            .chain(
+                self.open_upvalues
+                    .iter()
+                    .map(|&upvalue| Value::Object(upvalue)),
+            );
+        self.heap.collect(roots);
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> RloxResult {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        let Value::Object(object) = callee else {
+            return Err(self.runtime_error(
+                ErrorCode::NotCallable,
+                "Can only call functions and classes.",
+            ));
+        };
+        match self.heap.get(object) {
+            ObjectType::Closure(_) => self.call(object, arg_count),
+            &ObjectType::BoundMethod(BoundMethod { receiver, method }) => {
+                self.stack[callee_slot] = receiver;
+                self.call(method, arg_count)
+            }
+            ObjectType::Class(class) => {
+                let initializer = class.methods.get(&self.heap.key(self.init_string)).copied();
+                let instance = self.alloc(ObjectType::Instance(Instance::new(object)));
+                self.stack[callee_slot] = Value::Object(instance);
+                if let Some(initializer) = initializer {
+                    return self.call(initializer, arg_count);
+                }
+                if arg_count != 0 {
+                    return Err(self.runtime_error(
+                        ErrorCode::ArityMismatch,
+                        format!("Expected 0 arguments but got {arg_count}."),
+                    ));
+                }
+                Ok(())
+            }
+            _ => Err(self.runtime_error(
+                ErrorCode::NotCallable,
+                "Can only call functions and classes.",
+            )),
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its class's method `name` bound to it.
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> RloxResult {
+        let Some(&method) = self.heap.class(class).methods.get(&self.heap.key(name)) else {
+            return Err(self.runtime_error(
+                ErrorCode::UndefinedProperty,
+                format!("Undefined property '{}'.", self.heap.string(name)),
+            ));
+        };
+        let receiver = *self.stack.last().ok_or(Error::InvalidBytecode)?;
+        let bound = self.alloc(ObjectType::BoundMethod(BoundMethod { receiver, method }));
+        self.stack.pop();
+        self.stack.push(Value::Object(bound));
+        Ok(())
+    }
+
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> RloxResult {
+        let function = self.heap.closure(closure).function;
+        let arity = self.heap.function(function).arity;
+        if arg_count != arity {
+            return Err(self.runtime_error(
+                ErrorCode::ArityMismatch,
+                format!("Expected {arity} arguments but got {arg_count}."),
+            ));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error(ErrorCode::StackOverflow, "Stack overflow."));
+        }
+        let slots = self.stack.len() - arg_count - 1;
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots,
+        });
+        Ok(())
+    }
+
+    /// Returns the open upvalue for the stack slot at `slot`, creating one if the slot has not
+    /// been captured yet.
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let heap = &self.heap;
+        let position = self.open_upvalues.partition_point(
+            |&upvalue| matches!(heap.upvalue(upvalue), Upvalue::Open(s) if *s < slot),
+        );
+        if let Some(&upvalue) = self.open_upvalues.get(position) {
+            if matches!(heap.upvalue(upvalue), Upvalue::Open(s) if *s == slot) {
+                return upvalue;
+            }
+        }
+        let upvalue = self.alloc(ObjectType::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+
+    /// Moves every captured variable at or above the stack slot `last` off the stack and into
+    /// its upvalue.
+    fn close_upvalues(&mut self, last: usize) {
+        let heap = &self.heap;
+        let position = self.open_upvalues.partition_point(
+            |&upvalue| matches!(heap.upvalue(upvalue), Upvalue::Open(s) if *s < last),
+        );
+        for upvalue in self.open_upvalues.drain(position..) {
+            let upvalue = self.heap.upvalue_mut(upvalue);
+            if let Upvalue::Open(slot) = *upvalue {
+                *upvalue = Upvalue::Closed(self.stack[slot]);
+            }
+        }
     }
 }
@@ -0,0 +1,317 @@
+//! Round trips through `rlox compile` and `rlox run`.
+
+mod common;
+
+use common::{rlox, scratch, script};
+
+#[test]
+fn compiled_scripts_run_like_source() {
+    let source = "\
+fun counter() {
+  var n = 0;
+  fun next() { n = n + 1; return n; }
+  return next;
+}
+class Greeter { init(name) { this.name = name; } greet() { return \"hi \" + this.name; } }
+var c = counter();
+c();
+print c();
+print Greeter(\"lox\").greet();
+print -0.5 * 4;
+";
+    let script = script("roundtrip.lox", source);
+    let compiled = scratch("roundtrip.loxc");
+    assert!(rlox(&["compile", &script]).status.success());
+
+    let from_source = rlox(&[&script]);
+    let from_bytecode = rlox(&["run", &compiled]);
+    assert!(from_bytecode.status.success());
+    assert_eq!(from_bytecode.stdout, from_source.stdout);
+    assert_eq!(
+        String::from_utf8_lossy(&from_bytecode.stdout),
+        "2\nhi lox\n-2\n"
+    );
+}
+
+#[test]
+fn long_operands_reach_past_256_constants() {
+    // Every global takes two constants, its name and its value, so the later ones need the
+    // long forms.
+    let mut source: String = (0..300).map(|i| format!("var g{i} = {i};\n")).collect();
+    source.push_str("g299 = g299 + 1000;\nprint g299;\nprint g0 + g150;\n");
+    let path = script("long.lox", &source);
+
+    let ops = |level| {
+        let output = rlox(&["--disassemble", level, &path]);
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(2).map(str::to_owned))
+            .collect::<Vec<_>>()
+    };
+    let unoptimized = ops("-O0");
+    for op in [
+        "ConstantLong",
+        "DefineGlobalLong",
+        "GetGlobalLong",
+        "SetGlobalLong",
+    ] {
+        assert!(unoptimized.iter().any(|name| name == op), "no {op}");
+    }
+    assert!(ops("-O1").iter().any(|name| name == "AddConstantLong"));
+
+    let mut runs = vec![rlox(&["-O0", &path]), rlox(&["-O1", &path])];
+    for level in ["-O0", "-O1"] {
+        let compiled = scratch(&format!("long{level}.loxc"));
+        assert!(rlox(&["compile", level, &path, "-o", &compiled])
+            .status
+            .success());
+        runs.push(rlox(&["run", &compiled]));
+    }
+    for output in runs {
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1299\n150\n");
+    }
+}
+
+#[test]
+fn changed_sources_make_bytecode_stale() {
+    let path = script("stale.lox", "print 1;\n");
+    let compiled = scratch("stale.loxc");
+    assert!(rlox(&["compile", &path]).status.success());
+    assert!(rlox(&["run", &compiled]).stderr.is_empty());
+
+    script("stale.lox", "print 2;\n");
+    let output = rlox(&["run", &compiled]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("warning: {path} has changed since {compiled} was compiled from it\n")
+    );
+}
+
+/// Compiles `source` to a scratch `.loxc` file and returns its bytes.
+fn compile(name: &str, source: &str) -> Vec<u8> {
+    let script = script(&format!("{name}.lox"), source);
+    let compiled = scratch(&format!("{name}.loxc"));
+    assert!(rlox(&["compile", &script, "-o", &compiled])
+        .status
+        .success());
+    std::fs::read(compiled).expect("Could not read compiled file.")
+}
+
+#[test]
+fn truncated_files_are_rejected() {
+    let bytes = compile("truncated", "print 1;");
+    let truncated = script("truncated_cut.loxc", &bytes[..bytes.len() - 1]);
+    let output = rlox(&[&truncated]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: Bytecode file is truncated.\n"
+    );
+}
+
+#[test]
+fn tampered_files_fail_verification() {
+    let mut bytes = compile("tampered", "print 1;");
+    // The header is 10 bytes and the script's name flag, arity, upvalue count and code length
+    // another 8, so the operand of the first instruction, `Constant 0`, is at 19.
+    assert_eq!(bytes[19], 0);
+    bytes[19] = 5;
+    let tampered = script("tampered_bad.loxc", &bytes);
+    let output = rlox(&[&tampered]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: Invalid bytecode in script at offset 0: constant 5 is out of range.\n"
+    );
+    assert!(output.stdout.is_empty());
+}
+
+// Opcodes, by their position in `OpCode`.
+const CONSTANT: u8 = 0;
+const NIL: u8 = 2;
+const TRUE: u8 = 3;
+const POP: u8 = 5;
+const GET_GLOBAL: u8 = 6;
+const GET_UPVALUE: u8 = 14;
+const GET_SUPER: u8 = 20;
+const PRINT: u8 = 31;
+const JUMP: u8 = 32;
+const JUMP_IF_FALSE: u8 = 33;
+const RETURN: u8 = 39;
+const INHERIT: u8 = 42;
+const METHOD: u8 = 43;
+
+fn len(len: usize) -> [u8; 4] {
+    u32::try_from(len)
+        .expect("Test code is small.")
+        .to_le_bytes()
+}
+
+const HEADER: &[u8] = b"LOXC\x01\x00\x00\x00\x00\x00";
+
+/// Encodes an unnamed function with no parameters or upvalues, up to its constants.
+fn function_start(code: &[u8], constant_count: usize) -> Vec<u8> {
+    let mut bytes = vec![0, 0, 0, 0];
+    bytes.extend_from_slice(&len(code.len()));
+    bytes.extend_from_slice(code);
+    bytes.extend_from_slice(&len(constant_count));
+    bytes
+}
+
+/// Encodes what follows a function's constants: a single location, line 1, covering all the
+/// code.
+fn function_end() -> Vec<u8> {
+    let mut bytes = len(1).to_vec();
+    for field in [0, 1, 1, 0, 0] {
+        bytes.extend_from_slice(&len(field));
+    }
+    bytes
+}
+
+/// Encodes a script with `code` and `constants`, given already encoded, in the `.loxc` format.
+fn loxc(code: &[u8], constants: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = HEADER.to_vec();
+    bytes.extend(function_start(code, constants.len()));
+    for constant in constants {
+        bytes.extend_from_slice(constant);
+    }
+    bytes.extend(function_end());
+    bytes
+}
+
+fn number(value: f64) -> Vec<u8> {
+    let mut bytes = vec![0];
+    bytes.extend_from_slice(&value.to_le_bytes());
+    bytes
+}
+
+fn string(value: &str) -> Vec<u8> {
+    let mut bytes = vec![1];
+    bytes.extend_from_slice(&u32::try_from(value.len()).unwrap_or(0).to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+    bytes
+}
+
+/// Runs the bytecode and returns its exit code and what it wrote to stderr.
+fn run(name: &str, bytes: &[u8]) -> (Option<i32>, String) {
+    let output = rlox(&[&script(name, bytes)]);
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    (output.status.code(), stderr)
+}
+
+#[test]
+fn bad_jumps_fail_verification() {
+    let code = [JUMP, 0, 100, NIL, RETURN];
+    assert_eq!(
+        run("jump_out.loxc", &loxc(&code, &[])),
+        (
+            Some(65),
+            "error: Invalid bytecode in script at offset 0: jump target is out of range.\n"
+                .to_owned()
+        )
+    );
+
+    // Lands on the operand of the `Constant`.
+    let code = [JUMP, 0, 1, CONSTANT, 0, PRINT, NIL, RETURN];
+    assert_eq!(
+        run("jump_into.loxc", &loxc(&code, &[number(1.0)])),
+        (
+            Some(65),
+            "error: Invalid bytecode in script at offset 0: jump target 4 is not the start of an \
+             instruction.\n"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn mismatched_stack_depths_fail_verification() {
+    // The `Nil` at 5 is reached with the extra `Nil` pushed at 4 and, by the jump, without it.
+    let code = [TRUE, JUMP_IF_FALSE, 0, 1, NIL, NIL, RETURN];
+    assert_eq!(
+        run("depths.loxc", &loxc(&code, &[])),
+        (
+            Some(65),
+            "error: Invalid bytecode in script at offset 5: reached with 2 values on the stack \
+             but also with 3.\n"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn wrong_constant_types_fail_verification() {
+    let code = [GET_GLOBAL, 0, POP, NIL, RETURN];
+    assert_eq!(
+        run("constant_type.loxc", &loxc(&code, &[number(1.0)])),
+        (
+            Some(65),
+            "error: Invalid bytecode in script at offset 0: constant 0 is not a string.\n"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn class_instructions_on_other_values_are_errors() {
+    // Each takes two values that should include a class but are both the string "s".
+    for op in [GET_SUPER, METHOD, INHERIT] {
+        let code = [CONSTANT, 0, CONSTANT, 0, op, 0, POP, NIL, RETURN];
+        let code = if op == INHERIT {
+            [&code[..5], &code[6..]].concat()
+        } else {
+            code.to_vec()
+        };
+        assert_eq!(
+            run(&format!("class_op_{op}.loxc"), &loxc(&code, &[string("s")])),
+            (Some(70), "error: Invalid bytecode.\n".to_owned()),
+            "opcode {op}"
+        );
+    }
+}
+
+#[test]
+fn scripts_with_upvalues_fail_verification() {
+    // A script capturing local 0 of a function that does not exist.
+    let mut bytes = HEADER.to_vec();
+    bytes.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    let code = [GET_UPVALUE, 0, PRINT, NIL, RETURN];
+    bytes.extend_from_slice(&len(code.len()));
+    bytes.extend_from_slice(&code);
+    bytes.extend_from_slice(&len(0));
+    bytes.extend(function_end());
+    assert_eq!(
+        run("script_upvalue.loxc", &bytes),
+        (
+            Some(65),
+            "error: Invalid bytecode in script at offset 0: top-level code cannot have upvalues.\n"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn deeply_nested_functions_are_rejected() {
+    // Each function holds the next one as its only constant.
+    let depth = 100_000;
+    let mut start = function_start(&[NIL, RETURN], 1);
+    start.push(2);
+    let mut bytes = HEADER.to_vec();
+    for _ in 0..depth {
+        bytes.extend_from_slice(&start);
+    }
+    bytes.extend(function_start(&[NIL, RETURN], 0));
+    for _ in 0..=depth {
+        bytes.extend(function_end());
+    }
+    assert_eq!(
+        run("deep.loxc", &bytes),
+        (
+            Some(65),
+            "error: Malformed bytecode: functions nested too deeply.\n".to_owned()
+        )
+    );
+}
@@ -0,0 +1,49 @@
+//! Helpers shared by the integration tests.
+
+// Each test crate compiles its own copy of this module and uses only some of it.
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Path of the scratch file named `name`, which need not exist yet.
+pub fn scratch(name: &str) -> String {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(name)
+        .into_os_string()
+        .into_string()
+        .expect("Scratch path is not UTF-8.")
+}
+
+/// Writes `contents` to the scratch file named `name` and returns its path.
+pub fn script(name: &str, contents: impl AsRef<[u8]>) -> String {
+    let path = scratch(name);
+    std::fs::write(&path, contents).expect("Could not write scratch file.");
+    path
+}
+
+pub fn rlox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .output()
+        .expect("Could not run rlox.")
+}
+
+/// Runs rlox with `args`, feeding it `input` on stdin.
+pub fn rlox_with_input(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run rlox.");
+    child
+        .stdin
+        .take()
+        .expect("Stdin is piped.")
+        .write_all(input.as_bytes())
+        .expect("Could not write to rlox.");
+    child.wait_with_output().expect("Could not run rlox.")
+}
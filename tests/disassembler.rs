@@ -0,0 +1,79 @@
+//! Golden test for `--disassemble`.
+
+mod common;
+
+use common::{rlox, script};
+
+#[test]
+fn listing_shows_offsets_lines_jumps_and_captures() {
+    let source = "\
+fun outer(a) {
+  var b = 1;
+  fun inner() {
+    return a + b;
+  }
+  return inner;
+}
+var i = 0;
+while (i < 2) {
+  if (i == 1) print outer(i)();
+  i = i + 1;
+}
+";
+    let output = rlox(&["--disassemble", &script("disassemble.lox", source)]);
+    assert!(output.status.success());
+    // Nested functions follow the script in the order they were declared.
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+== <script> == 6 constants
+0000    7 Closure             1 '<fn outer>'
+0002    | DefineGlobal        0 'outer'
+0004    8 Constant            3 '0'
+0006    | DefineGlobal        2 'i'
+0008    9 GetGlobal           2 'i'
+0010    | Constant            4 '2'
+0012    | Less
+0013    | JumpIfFalse        33 -> 49
+0016    | Pop
+0017   10 GetGlobal           2 'i'
+0019    | Constant            5 '1'
+0021    | Equal
+0022    | JumpIfFalse        13 -> 38
+0025    | Pop
+0026    | GetGlobal           0 'outer'
+0028    | GetGlobal           2 'i'
+0030    | Call                1
+0032    | Call                0
+0034    | Print
+0035    | Jump                1 -> 39
+0038    | Pop
+0039   11 GetGlobal           2 'i'
+0041    | AddConstant         5 '1'
+0043    | SetGlobal           2 'i'
+0045    | Pop
+0046   12 Loop               41 -> 8
+0049    | Pop
+0050   13 Nil
+0051    | Return
+
+== outer == 2 constants
+0000    2 Constant            0 '1'
+0002    5 Closure             1 '<fn inner>'
+0002    |                  local 1
+0002    |                  local 2
+0004    6 GetLocal            3
+0006    | Return
+0007    7 Nil
+0008    | Return
+
+== inner == 0 constants
+0000    4 GetUpvalue          0
+0002    | GetUpvalue          1
+0004    | Add
+0005    | Return
+0006    5 Nil
+0007    | Return
+"
+    );
+}
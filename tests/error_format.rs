@@ -0,0 +1,66 @@
+//! Locks the schema of `--error-format=json`. Editors and CI parse these lines, so any change
+//! to the expected strings below is a breaking change.
+
+mod common;
+
+use common::{rlox, scratch, script};
+
+/// Runs `rlox --error-format=json` on `path` and returns the JSON lines it wrote to stderr.
+fn json_errors(path: &str) -> Vec<String> {
+    let output = rlox(&["--error-format=json", path]);
+    String::from_utf8(output.stderr)
+        .expect("rlox wrote invalid UTF-8.")
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn compile_errors_are_one_object_per_line() {
+    let path = script("json_compile.lox", "var a = 1;\nprint a +;\nvar = 2;\n");
+    let file = &path;
+    assert_eq!(
+        json_errors(&path),
+        [
+            format!(
+                r#"{{"severity":"error","code":"L0005","message":"Expect prefix expression.","file":"{file}","line":2,"column":10,"span":{{"start":20,"end":21}},"backtrace":[]}}"#
+            ),
+            format!(
+                r#"{{"severity":"error","code":"L0004","message":"Expect variable name.","file":"{file}","line":3,"column":5,"span":{{"start":26,"end":27}},"backtrace":[]}}"#
+            ),
+        ]
+    );
+}
+
+#[test]
+fn runtime_errors_carry_a_backtrace() {
+    let source = "fun outer() {\n  inner();\n}\nfun inner() {\n  return -nil;\n}\nouter();\n";
+    let path = script("json_runtime.lox", source);
+    let file = &path;
+    assert_eq!(
+        json_errors(&path),
+        [format!(
+            r#"{{"severity":"error","code":"L0104","message":"Operand must be a number.","file":"{file}","line":5,"column":10,"span":{{"start":50,"end":51}},"backtrace":[{{"function":"inner","line":5,"column":10}},{{"function":"outer","line":2,"column":8}},{{"function":null,"line":7,"column":6}}]}}"#
+        )]
+    );
+}
+
+#[test]
+fn errors_without_a_location_use_null() {
+    let path = scratch("does \"not\" exist.lox");
+    let errors = json_errors(&path);
+    assert_eq!(errors.len(), 1);
+    let file = path.replace('"', "\\\"");
+    let expected = format!(r#"{{"severity":"error","code":null,"message":"couldn't read {file}: "#);
+    assert!(errors[0].starts_with(&expected), "{}", errors[0]);
+    let expected =
+        format!(r#","file":"{file}","line":null,"column":null,"span":null,"backtrace":[]}}"#);
+    assert!(errors[0].ends_with(&expected), "{}", errors[0]);
+}
+
+#[test]
+fn successful_runs_report_nothing() {
+    let path = script("json_ok.lox", "print 1 + 2;\n");
+    assert!(json_errors(&path).is_empty());
+}
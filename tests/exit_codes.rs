@@ -0,0 +1,81 @@
+//! What rlox prints and exits with when a script fails.
+
+mod common;
+
+use common::{rlox, scratch, script};
+
+/// Runs `path` without color and returns its exit code and what it printed to stderr.
+fn fail(path: &str) -> (Option<i32>, String) {
+    let output = rlox(&["--color=never", path]);
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn compile_errors_exit_65() {
+    let (code, stderr) = fail(&script("exit_compile.lox", "print 1;\nvar = 2;\n"));
+    assert_eq!(code, Some(65));
+    assert!(
+        stderr.starts_with("error[L0004]: Expect variable name."),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn runtime_errors_exit_70_with_a_line_per_frame() {
+    let source = "\
+fun inner() {
+  return 1 + nil;
+}
+fun outer() {
+  inner();
+}
+print \"before\";
+outer();
+print \"after\";
+";
+    let path = script("exit_runtime.lox", source);
+    let output = rlox(&["--color=never", &path]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "before\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let trace: Vec<_> = stderr
+        .lines()
+        .filter(|line| line.starts_with('['))
+        .collect();
+    assert_eq!(
+        trace,
+        [
+            "[line 2] in inner()",
+            "[line 5] in outer()",
+            "[line 8] in script"
+        ]
+    );
+}
+
+#[test]
+fn stack_overflow_exits_70() {
+    let (code, stderr) = fail(&script("exit_overflow.lox", "fun f() { f(); }\nf();\n"));
+    assert_eq!(code, Some(70));
+    assert!(
+        stderr.starts_with("error[L0107]: Stack overflow."),
+        "{stderr}"
+    );
+    assert!(
+        stderr.ends_with("[line 1] in f()\n[line 2] in script\n"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn unreadable_scripts_exit_74() {
+    let path = scratch("exit_missing.lox");
+    let (code, stderr) = fail(&path);
+    assert_eq!(code, Some(74));
+    assert!(
+        stderr.starts_with(&format!("error: couldn't read {path}: ")),
+        "{stderr}"
+    );
+}
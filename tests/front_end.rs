@@ -0,0 +1,106 @@
+//! Golden tests for `--tokens` and `--ast`, which show what the scanner and the parser made of
+//! a script.
+
+mod common;
+
+use common::{rlox, script};
+
+/// Runs `rlox <flag>` on `source` and returns what it printed.
+fn dump(flag: &str, name: &str, source: &str) -> String {
+    let output = rlox(&[flag, &script(name, source)]);
+    assert!(output.status.success(), "rlox {flag} failed on {source:?}");
+    String::from_utf8(output.stdout).expect("rlox wrote invalid UTF-8.")
+}
+
+#[test]
+fn tokens_show_kind_lexeme_and_span() {
+    let tokens = dump("--tokens", "tokens.lox", "a <= b >= c;\n\"s\"");
+    assert_eq!(
+        tokens,
+        "\
+1:1      Identifier   'a' 0..1
+1:3      LessEqual    '<=' 2..4
+1:6      Identifier   'b' 5..6
+1:8      GreaterEqual '>=' 7..9
+1:11     Identifier   'c' 10..11
+1:12     Semicolon    ';' 11..12
+2:1      String       '\"s\"' 13..16
+2:4      Eof          '' 16..16
+"
+    );
+}
+
+#[test]
+fn columns_count_characters_from_the_line_start() {
+    let tokens = dump("--tokens", "columns.lox", "\"é\nx\" é;\n  y");
+    // The string's lexeme runs onto a second line of the dump.
+    let positions: Vec<_> = tokens
+        .lines()
+        .filter(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+        .map(|line| line.split_whitespace().next().unwrap_or_default())
+        .collect();
+    assert_eq!(positions, ["1:1", "2:4", "2:5", "3:3", "3:4"]);
+}
+
+#[test]
+fn keywords_must_match_the_whole_identifier() {
+    let tokens = dump("--tokens", "keywords.lox", "classy fun funny");
+    let kinds: Vec<_> = tokens
+        .lines()
+        .map(|line| line.split_whitespace().nth(1).unwrap_or_default())
+        .collect();
+    assert_eq!(kinds, ["Identifier", "Fun", "Identifier", "Eof"]);
+}
+
+#[test]
+fn numbers_take_a_dot_only_before_a_digit() {
+    let tokens = dump("--tokens", "numbers.lox", "1.2.3 1..2 4.");
+    let lexemes: Vec<_> = tokens
+        .lines()
+        .map(|line| line.split_whitespace().nth(2).unwrap_or_default())
+        .collect();
+    assert_eq!(
+        lexemes,
+        ["'1.2'", "'.'", "'3'", "'1'", "'.'", "'.'", "'2'", "'4'", "'.'", "''"]
+    );
+
+    // They are property accesses on a number, so they fail to compile rather than panic.
+    for source in ["print 1.2.3;", "print 1..2;"] {
+        let output = rlox(&[&script("bad_number.lox", source)]);
+        assert_eq!(output.status.code(), Some(65), "{source}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Expect property name after '.'."),
+            "{stderr}"
+        );
+    }
+}
+
+#[test]
+fn ast_shows_precedence_and_statements() {
+    let ast = dump(
+        "--ast",
+        "ast.lox",
+        "var x = 1 + 2 * -3;\nif (x) print x; else x = nil;\n",
+    );
+    assert_eq!(
+        ast,
+        "\
+Script
+  Var x
+    Binary +
+      Number 1
+      Binary *
+        Number 2
+        Unary -
+          Number 3
+  If
+    Variable x
+    Print
+      Variable x
+    Expression
+      Assign x
+        Literal nil
+"
+    );
+}
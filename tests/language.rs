@@ -0,0 +1,234 @@
+//! Scripts exercising the language, checked against what clox prints for them.
+
+mod common;
+
+use common::{rlox, script};
+
+/// Runs `source`, plainly and with a collection before every allocation, checks that both
+/// succeeded with the same output and returns it.
+fn run(name: &str, source: &str) -> String {
+    let path = script(name, source);
+    let output = rlox(&[&path]);
+    let stressed = rlox(&["--gc-stress", &path]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stressed.status.success(),
+        "{}",
+        String::from_utf8_lossy(&stressed.stderr)
+    );
+    assert_eq!(output.stdout, stressed.stdout);
+    String::from_utf8(output.stdout).expect("rlox wrote invalid UTF-8.")
+}
+
+#[test]
+fn locals_shadow_and_go_out_of_scope() {
+    let source = "\
+var a = \"global\";
+{
+  var a = \"outer\";
+  {
+    var a = \"inner\";
+    print a;
+  }
+  print a;
+  a = \"assigned\";
+  print a;
+}
+print a;
+";
+    assert_eq!(
+        run("locals.lox", source),
+        "inner\nouter\nassigned\nglobal\n"
+    );
+}
+
+#[test]
+fn control_flow() {
+    let source = "\
+if (nil) print 1; else print 2;
+if (0) print \"0 is truthy\";
+print nil or \"or\";
+print false and 1;
+var i = 0;
+while (i < 3) { print i; i = i + 1; }
+for (var j = 10; j > 7; j = j - 1) print j;
+var k = 0;
+for (; k < 2;) k = k + 1;
+print k;
+";
+    assert_eq!(
+        run("control_flow.lox", source),
+        "2\n0 is truthy\nor\nfalse\n0\n1\n2\n10\n9\n8\n2\n"
+    );
+}
+
+#[test]
+fn functions_recurse_and_return() {
+    let source = "\
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+print fib(15);
+fun noReturn() {}
+print noReturn();
+print fib;
+";
+    assert_eq!(run("functions.lox", source), "610\nnil\n<fn fib>\n");
+}
+
+#[test]
+fn closures_capture_variables_not_values() {
+    let source = "\
+var get;
+var set;
+fun pair() {
+  var shared = \"initial\";
+  fun g() { print shared; }
+  fun s(v) { shared = v; }
+  get = g;
+  set = s;
+}
+pair();
+get();
+set(\"updated\");
+get();
+
+fun counter() {
+  var n = 0;
+  fun inc() { n = n + 1; return n; }
+  return inc;
+}
+var a = counter();
+var b = counter();
+print a();
+print a();
+print b();
+
+fun outer() {
+  var x = \"outer\";
+  fun middle() {
+    fun inner() { print x; }
+    return inner;
+  }
+  return middle();
+}
+outer()();
+
+{
+  var local = \"before\";
+  fun show() { print local; }
+  local = \"after\";
+  show();
+}
+";
+    assert_eq!(
+        run("closures.lox", source),
+        "initial\nupdated\n1\n2\n1\nouter\nafter\n"
+    );
+}
+
+#[test]
+fn closures_in_loops() {
+    // A variable declared in the body is new each time round, but the loop variable is one
+    // variable for the whole loop, as in clox.
+    let source = "\
+var f1;
+var f2;
+var f3;
+for (var i = 1; i <= 3; i = i + 1) {
+  var j = i;
+  fun f() { print j; }
+  if (i == 1) f1 = f; else if (i == 2) f2 = f; else f3 = f;
+}
+f1();
+f2();
+f3();
+
+var first;
+for (var k = 0; k < 3; k = k + 1) {
+  fun g() { print k; }
+  if (k == 0) first = g;
+}
+first();
+";
+    assert_eq!(run("loop_closures.lox", source), "1\n2\n3\n3\n");
+}
+
+#[test]
+fn classes_have_fields_methods_and_initializers() {
+    let source = "\
+class Pair {
+  init(a, b) { this.a = a; this.b = b; }
+  sum() { return this.a + this.b; }
+}
+var p = Pair(1, 2);
+print p.sum();
+p.a = 10;
+var bound = p.sum;
+print bound();
+print p;
+print Pair;
+print p.init(3, 4) == p;
+";
+    assert_eq!(
+        run("classes.lox", source),
+        "3\n12\nPair instance\nPair\ntrue\n"
+    );
+}
+
+#[test]
+fn subclasses_inherit_and_call_super() {
+    let source = "\
+class A {
+  greet() { return \"A:\" + this.name(); }
+  name() { return \"a\"; }
+}
+class B < A {
+  name() { return \"b\"; }
+  greet() { return \"B+\" + super.greet(); }
+}
+print B().greet();
+var method = B().greet;
+print method();
+";
+    assert_eq!(run("inheritance.lox", source), "B+A:b\nB+A:b\n");
+}
+
+#[test]
+fn collection_keeps_what_is_reachable() {
+    // Everything live is reachable only through the stack, globals, upvalues or fields while
+    // garbage piles up around it.
+    let source = "\
+class Node { init(value, next) { this.value = value; this.next = next; } }
+fun keep(s) { fun get() { return s; } return get; }
+var list = nil;
+var kept = keep(\"kept\" + \"!\");
+for (var i = 0; i < 200; i = i + 1) {
+  var garbage = kept() + \"?\";
+  list = Node(i, list);
+}
+var sum = 0;
+while (list != nil) { sum = sum + list.value; list = list.next; }
+print sum;
+print kept();
+";
+    assert_eq!(run("gc.lox", source), "19900\nkept!\n");
+}
+
+#[test]
+fn strings_are_interned() {
+    let source = "\
+var a = \"ab\";
+var b = \"a\" + \"b\";
+var c = \"a\";
+print a == b;
+print c + \"b\" == a;
+print \"a\" == \"b\";
+";
+    assert_eq!(run("interning.lox", source), "true\ntrue\nfalse\n");
+}
@@ -0,0 +1,151 @@
+//! Checks that compile-time optimizations change the code but not what it does.
+
+mod common;
+
+use std::process::Output;
+
+use common::{rlox, script};
+
+#[test]
+fn literal_operands_are_folded() {
+    let source = "\
+print 60 * 60 * 24;
+print -(1 / 0);
+print \"a\" + \"b\";
+print !(0 / 0 >= 1);
+";
+    let path = script("fold.lox", source);
+    let output = rlox(&[&path]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "86400\n-inf\nab\nfalse\n"
+    );
+
+    let disassembly = rlox(&["--disassemble", &path]);
+    let disassembly = String::from_utf8_lossy(&disassembly.stdout);
+    // Only the results stay in the pool, not the operands they were folded from.
+    assert_eq!(
+        disassembly.lines().next(),
+        Some("== <script> == 3 constants")
+    );
+    let ops: Vec<_> = disassembly
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().nth(2).unwrap_or_default())
+        .collect();
+    assert_eq!(
+        ops,
+        [
+            "Constant", "Print", "Constant", "Print", "Constant", "Print", "False", "Print", "Nil",
+            "Return"
+        ]
+    );
+}
+
+#[test]
+fn type_errors_are_not_folded() {
+    let output = rlox(&[&script("fold_error.lox", "print 1;\nprint -\"a\";\n")]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Operand must be a number."));
+}
+
+/// Exercises every rewrite, including ones next to jump targets, which must be left alone.
+const PEEPHOLE: &str = "\
+var a = 1;
+var b = \"b\";
+var nan = 0 / 0;
+print a != 2;
+print a >= 2;
+print a <= 2;
+print nan >= 1;
+print nan <= 1;
+print a + 1;
+print b + \"c\";
+a;
+for (var i = 0; i <= 3; i = i + 1) { if (i != 2) print i; else a; }
+while (a <= 5) { a = a + 1; b; }
+print (a or b);
+fun f(x) { return x + 10; }
+print f(a);
+{ var l = 1; l; 2; nil; true; false; fun g() { l; return l; } print g(); }
+undefinedVar;
+print \"unreachable\";
+";
+
+/// Runs `source` at `-O0` and `-O1`, checks that both did the same and returns the `-O1` run.
+fn run_both(name: &str, source: &str) -> Output {
+    let path = script(name, source);
+    let unoptimized = rlox(&["-O0", &path]);
+    let optimized = rlox(&["-O1", &path]);
+    assert_eq!(optimized.status.code(), unoptimized.status.code());
+    assert_eq!(optimized.stdout, unoptimized.stdout);
+    assert_eq!(optimized.stderr, unoptimized.stderr);
+    optimized
+}
+
+#[test]
+fn optimized_and_unoptimized_runs_match() {
+    let output = run_both("peephole.lox", PEEPHOLE);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "true\nfalse\ntrue\ntrue\ntrue\n2\nbc\n0\n1\n3\n6\n16\n1\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Undefined variable 'undefinedVar'."));
+
+    // `AddConstant` fails like the `Add` it replaces.
+    let output = run_both("peephole_add.lox", "var b = \"b\";\nprint b + 1;\n");
+    assert_eq!(output.status.code(), Some(70));
+}
+
+#[test]
+fn superinstructions_replace_pairs() {
+    let path = script("superinstructions.lox", PEEPHOLE);
+    let ops = |level| {
+        let output = rlox(&[level, "--disassemble", &path]);
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(2).map(str::to_owned))
+            .collect::<Vec<_>>()
+    };
+    let unoptimized = ops("-O0");
+    let optimized = ops("-O1");
+    for op in ["NotEqual", "GreaterEqual", "LessEqual", "AddConstant"] {
+        assert!(!unoptimized.iter().any(|name| name == op));
+        assert!(
+            optimized.iter().any(|name| name == op),
+            "no {op} in {optimized:?}"
+        );
+    }
+    assert!(optimized.len() < unoptimized.len());
+
+    // Values pushed only to be popped are gone, except a global's, whose lookup can fail.
+    let dead = |ops: &[String]| {
+        ops.windows(2)
+            .filter(|pair| pair[1] == "Pop")
+            .map(|pair| pair[0].clone())
+            .filter(|op| {
+                ["GetLocal", "GetUpvalue", "Constant", "Nil", "True", "False"]
+                    .contains(&op.as_str())
+            })
+            .count()
+    };
+    assert!(dead(&unoptimized) >= 6);
+    assert_eq!(dead(&optimized), 0);
+    assert!(optimized
+        .windows(2)
+        .any(|pair| pair[0] == "GetGlobal" && pair[1] == "Pop"));
+}
+
+#[test]
+fn constants_are_pooled_once() {
+    let source = "var x = 1;\nprint x + x;\nprint x;\nprint \"a\" + \"a\";\nprint 0;\nprint -0;\n";
+    let output = rlox(&["--disassemble", &script("pool.lox", source)]);
+    let disassembly = String::from_utf8_lossy(&output.stdout);
+    // `x`, `1`, `"aa"`, `0` and `-0`, which is kept apart from `0`. The folded `"a"` is gone.
+    assert_eq!(
+        disassembly.lines().next(),
+        Some("== <script> == 5 constants")
+    );
+}
@@ -0,0 +1,82 @@
+//! Sessions fed to the REPL on stdin.
+
+mod common;
+
+use common::rlox_with_input;
+
+/// Runs a REPL session on `input` and returns what it printed to stdout and stderr.
+fn session(input: &str) -> (String, String) {
+    let output = rlox_with_input(&["--color=never"], input);
+    assert!(output.status.success());
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn errors_in_earlier_entries_show_their_own_source() {
+    let (_, stderr) = session("fun f() { return -\"x\"; }\nprint  \"éééééééééé\"; f();\n");
+    assert_eq!(
+        stderr,
+        "\
+error[L0104]: Operand must be a number.
+ --> <stdin>:1:18
+  |
+1 | fun f() { return -\"x\"; }
+  |                  ^
+[line 1] in f()
+[line 2] in script
+"
+    );
+}
+
+#[test]
+fn lines_count_from_the_start_of_the_session() {
+    let output = rlox_with_input(&["--error-format=json"], "var a = 1;\nprint -nil;\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(r#""line":2,"column":7,"span":{"start":17,"end":18}"#),
+        "{stderr}"
+    );
+}
+
+/// What the entries printed, without the prompts in front of them.
+fn printed(stdout: &str) -> Vec<String> {
+    stdout
+        .replace("... ", "")
+        .replace("> ", "")
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn state_survives_errors() {
+    let (stdout, stderr) =
+        session("var a = 1;\nprint -nil;\nvar = ;\nprint a;\na = a + 1;\nprint a;\n");
+    // Expression statements echo their value.
+    assert_eq!(printed(&stdout), ["1", "2", "2"]);
+    assert!(stderr.contains("Operand must be a number."), "{stderr}");
+    assert!(stderr.contains("Expect variable name."), "{stderr}");
+}
+
+#[test]
+fn open_brackets_continue_the_entry() {
+    let (stdout, stderr) = session("fun add(x) {\n  return x +\n    1;\n}\nprint (add(1) *\n3);\n");
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, "> ... ... ... > ... 6\n> \n");
+}
+
+#[test]
+fn closures_keep_captures_after_runtime_errors() {
+    let (stdout, stderr) = session(
+        "var g;\n{ var a; var b; var c; var d; var x = \"captured\"; fun f() { print x; } g = f; nil + 1; }\ng();\n",
+    );
+    assert!(
+        stderr.contains("Operands must be two numbers or two strings."),
+        "{stderr}"
+    );
+    assert_eq!(printed(&stdout), ["captured", "nil"]);
+}
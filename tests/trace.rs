@@ -0,0 +1,30 @@
+//! Checks `--trace` and `--trace-out`.
+
+mod common;
+
+use common::{rlox, scratch, script};
+
+#[test]
+fn trace_out_logs_each_instruction() {
+    let path = script("trace.lox", "print 1;\n");
+    let trace = scratch("trace.txt");
+    let output = rlox(&["--trace-out", &trace, &path]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    let trace = std::fs::read_to_string(trace).unwrap();
+    let mut lines = trace.lines();
+    assert_eq!(lines.next(), Some("          [ <script> ]"));
+    assert_eq!(lines.next(), Some("0000    1 Constant            0 '1'"));
+}
+
+#[test]
+fn trace_write_failures_name_the_trace() {
+    let path = script("trace_full.lox", "print 1;\n");
+    let output = rlox(&["--color=never", "--trace-out", "/dev/full", &path]);
+    assert_eq!(output.status.code(), Some(74));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("error: couldn't write trace to /dev/full: "),
+        "{stderr}"
+    );
+}